@@ -0,0 +1,421 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+use rtnetlink::packet_route::AddressFamily;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{
+    config::{get_config, CliOptions, Config, Filter, HostConfig, Metrics, Retry},
+    ddns::{get_dns_provider, provider_name, rtype},
+    netlink,
+    state::{record_key, RecordState, StateFile},
+    updater::{Outcome, Status, Updater},
+};
+
+/// Exit status of `sync --once` when every record was already up to date.
+pub const EXIT_UNCHANGED: i32 = 0;
+/// Exit status of `sync --once` when any record failed to sync.
+pub const EXIT_FAILED: i32 = 1;
+/// Exit status of `sync --once` when any record was changed.
+pub const EXIT_UPDATED: i32 = 2;
+
+/// Validate the config file, the configured interfaces and the provider
+/// settings, printing a report.
+///
+/// With `online` the published records are also fetched, which proves the
+/// credentials work without changing anything.
+pub async fn check(cli: &CliOptions, online: bool) -> Result<()> {
+    let config = get_config(&cli.config, cli.format)?;
+    println!("Config OK");
+
+    let mut problems = 0;
+
+    for iface in config.interfaces() {
+        for family in config.families(&iface) {
+            match netlink::get_if_addrs(&iface, family).await {
+                Ok(addrs) if addrs.is_empty() => {
+                    println!("Interface {iface}: no {} address yet", rtype(family));
+                }
+                Ok(addrs) => {
+                    let addrs = addrs.iter()
+                        .map(|a| a.ip.to_string())
+                        .collect::<Vec<_>>();
+                    println!("Interface {iface}: {}", addrs.join(", "));
+                }
+                Err(err) => {
+                    eprintln!("Interface {iface}: {err:#}");
+                    problems += 1;
+                }
+            }
+        }
+    }
+
+    for host in config.hosts() {
+        let fqdn = host.fqdn();
+        let client = match get_dns_provider(&config, &host.domain) {
+            Ok(client) => client,
+            Err(err) => {
+                eprintln!("Host {fqdn}: {err:#}");
+                problems += 1;
+                continue;
+            }
+        };
+        if !online {
+            println!("Host {fqdn}: OK");
+            continue;
+        }
+
+        for family in host.ip_version.families() {
+            match client.get_addr_record(&host.name, family).await {
+                Ok(Some(ip)) => println!("Host {fqdn}: {} record is {ip}", rtype(family)),
+                Ok(None) => println!("Host {fqdn}: no {} record", rtype(family)),
+                Err(err) => {
+                    eprintln!("Host {fqdn}: failed to fetch {} record: {err:#}", rtype(family));
+                    problems += 1;
+                }
+            }
+        }
+    }
+
+    if problems > 0 {
+        bail!("Found {problems} problem(s)");
+    }
+    Ok(())
+}
+
+/// Publish the current interface addresses once and wait for the updates
+/// to finish, returning the exit status.
+///
+/// Records that couldn't be updated, or that had no address to publish,
+/// count as failures.
+pub async fn sync_once(config: &Config) -> Result<i32> {
+    let updater = Updater::new(config)?;
+    updater.publish_current().await;
+
+    let statuses = updater.settle().await;
+    for (host, family, status) in &statuses {
+        if *status == Status::Idle {
+            warn!("No address to publish for {} {} record", host.fqdn(), rtype(*family));
+        }
+    }
+
+    let status = exit_status(statuses.into_iter().map(|(_, _, status)| status));
+    info!("Sync finished with status {status}");
+    Ok(status)
+}
+
+/// The exit status for a sync with the given record statuses.
+///
+/// Any failed or idle record makes the sync fail; otherwise it counts as
+/// updated if any record was changed.
+fn exit_status(statuses: impl IntoIterator<Item = Status>) -> i32 {
+    let mut failed = false;
+    let mut updated = false;
+    for status in statuses {
+        match status {
+            Status::Synced(Outcome::Unchanged | Outcome::Skipped) => {}
+            Status::Synced(_) => updated = true,
+            Status::Failed | Status::Idle => failed = true,
+        }
+    }
+
+    if failed {
+        EXIT_FAILED
+    } else if updated {
+        EXIT_UPDATED
+    } else {
+        EXIT_UNCHANGED
+    }
+}
+
+/// The configured hosts matching `host`, by name or FQDN, or all hosts.
+fn select_hosts(config: &Config, host: &Option<String>) -> Result<Vec<HostConfig>> {
+    let hosts = config.hosts().into_iter()
+        .filter(|h| host.as_ref().is_none_or(|name| *name == h.name || *name == h.fqdn()))
+        .collect::<Vec<_>>();
+    if hosts.is_empty() {
+        bail!("No configured host matches `{}`", host.as_deref().unwrap_or_default());
+    }
+    Ok(hosts)
+}
+
+fn family_of(ip: &IpAddr) -> AddressFamily {
+    match ip {
+        IpAddr::V4(_) => AddressFamily::Inet,
+        IpAddr::V6(_) => AddressFamily::Inet6,
+    }
+}
+
+/// Record a manual change in the state file, so the daemon doesn't trust a
+/// stale address on restart.
+fn save_state(config: &Config, host: &HostConfig, family: AddressFamily, state: RecordState) {
+    if config.dry_run {
+        return;
+    }
+    if let Some(path) = &config.state_file
+        && let Err(err) = StateFile::load(path).set(record_key(host, family), state)
+    {
+        warn!("Failed to save state: {err:#}");
+    }
+}
+
+fn finish(config: &Config, problems: usize) -> Result<()> {
+    if config.dry_run {
+        println!("Dry run; no changes were made");
+    }
+    if problems > 0 {
+        bail!("{problems} operation(s) failed");
+    }
+    Ok(())
+}
+
+/// Print the published records of the selected hosts.
+pub async fn get(config: &Config, host: &Option<String>) -> Result<()> {
+    let mut problems = 0;
+    for host in select_hosts(config, host)? {
+        let client = get_dns_provider(config, &host.domain)?;
+        for family in host.ip_version.families() {
+            match client.get_addr_record(&host.name, family).await {
+                Ok(Some(ip)) => println!("{} {} {ip}", host.fqdn(), rtype(family)),
+                Ok(None) => println!("{} {} none", host.fqdn(), rtype(family)),
+                Err(err) => {
+                    eprintln!("{} {}: {err:#}", host.fqdn(), rtype(family));
+                    problems += 1;
+                }
+            }
+        }
+    }
+    if problems > 0 {
+        bail!("{problems} operation(s) failed");
+    }
+    Ok(())
+}
+
+/// Create or update the records of the selected hosts to point at `ip`.
+pub async fn set(config: &Config, host: &Option<String>, ip: IpAddr) -> Result<()> {
+    let family = family_of(&ip);
+    let mut problems = 0;
+    for host in hosts_for_family(select_hosts(config, host)?, family)? {
+        let client = get_dns_provider(config, &host.domain)?;
+        let result = match client.get_addr_record(&host.name, family).await {
+            Ok(None) => client.create_addr_record(&host.name, &ip).await
+                .map(|()| "created"),
+            Ok(Some(current)) if current == ip => Ok("unchanged"),
+            Ok(Some(_)) => client.update_addr_record(&host.name, &ip).await
+                .map(|()| "updated"),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(outcome) => {
+                println!("{} {} {ip} ({outcome})", host.fqdn(), rtype(family));
+                save_state(config, &host, family, RecordState::new(Some(ip), outcome));
+            }
+            Err(err) => {
+                eprintln!("{} {}: {err:#}", host.fqdn(), rtype(family));
+                problems += 1;
+            }
+        }
+    }
+    finish(config, problems)
+}
+
+/// The hosts in `hosts` that publish `family` records.
+fn hosts_for_family(hosts: Vec<HostConfig>, family: AddressFamily) -> Result<Vec<HostConfig>> {
+    let (hosts, skipped): (Vec<_>, Vec<_>) = hosts.into_iter()
+        .partition(|h| h.ip_version.families().contains(&family));
+    for host in &skipped {
+        eprintln!("{} doesn't publish {} records; skipping", host.fqdn(), rtype(family));
+    }
+    if hosts.is_empty() {
+        bail!("None of the selected hosts publish {} records", rtype(family));
+    }
+    Ok(hosts)
+}
+
+/// Delete the records of the selected hosts.
+pub async fn delete(config: &Config, host: &Option<String>) -> Result<()> {
+    let mut problems = 0;
+    for host in select_hosts(config, host)? {
+        let client = get_dns_provider(config, &host.domain)?;
+        for family in host.ip_version.families() {
+            match client.delete_addr_record(&host.name, family).await {
+                Ok(()) => {
+                    println!("{} {} deleted", host.fqdn(), rtype(family));
+                    save_state(config, &host, family, RecordState::new(None, "deleted"));
+                }
+                Err(err) => {
+                    eprintln!("{} {}: {err:#}", host.fqdn(), rtype(family));
+                    problems += 1;
+                }
+            }
+        }
+    }
+    finish(config, problems)
+}
+
+#[derive(Serialize)]
+struct ProviderSummary {
+    name: &'static str,
+    credentials: &'static str,
+}
+
+/// The effective configuration, as shown by `print-config`.
+#[derive(Serialize)]
+struct Effective<'a> {
+    log_level: &'a Option<String>,
+    dry_run: bool,
+    provider: ProviderSummary,
+    filter: &'a Filter,
+    retry: &'a Retry,
+    reconcile_interval: Option<u64>,
+    state_file: &'a Option<String>,
+    metrics: &'a Option<Metrics>,
+    /// The hosts with all defaults and overrides applied
+    hosts: Vec<HostConfig>,
+}
+
+impl<'a> Effective<'a> {
+    fn new(config: &'a Config) -> Self {
+        Effective {
+            log_level: &config.log_level,
+            dry_run: config.dry_run,
+            provider: ProviderSummary {
+                name: provider_name(&config.ddns.provider),
+                credentials: "***",
+            },
+            filter: &config.ddns.filter,
+            retry: &config.retry,
+            reconcile_interval: config.reconcile_interval,
+            state_file: &config.state_file,
+            metrics: &config.metrics,
+            hosts: config.hosts(),
+        }
+    }
+}
+
+/// Print the effective config as JSON, with any secret references resolved
+/// and the provider credentials redacted.
+pub fn print_config(config: &Config) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&Effective::new(config))?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_hosts() -> Result<()> {
+        let config = corn::from_str::<Config>(r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    hosts = [ "www" "mail" { name = "www" domain = "example.org" } ]
+                }
+            } "#)?;
+
+        assert_eq!(select_hosts(&config, &None)?.len(), 3);
+
+        let hosts = select_hosts(&config, &Some("www".to_string()))?;
+        assert_eq!(hosts.iter().map(HostConfig::fqdn).collect::<Vec<_>>(),
+                   vec!["www.example.com", "www.example.org"]);
+
+        let hosts = select_hosts(&config, &Some("www.example.org".to_string()))?;
+        assert_eq!(hosts.iter().map(HostConfig::fqdn).collect::<Vec<_>>(),
+                   vec!["www.example.org"]);
+
+        assert!(select_hosts(&config, &Some("ftp".to_string())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hosts_for_family() -> Result<()> {
+        let config = corn::from_str::<Config>(r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    hosts = [ "www" { name = "v6" ip_version = "v6" } { name = "both" ip_version = "both" } ]
+                }
+            } "#)?;
+
+        let names = |hosts: Vec<HostConfig>| hosts.into_iter().map(|h| h.name).collect::<Vec<_>>();
+        assert_eq!(names(hosts_for_family(config.hosts(), AddressFamily::Inet)?), vec!["www", "both"]);
+        assert_eq!(names(hosts_for_family(config.hosts(), AddressFamily::Inet6)?), vec!["v6", "both"]);
+
+        let www = select_hosts(&config, &Some("www".to_string()))?;
+        assert!(hosts_for_family(www, AddressFamily::Inet6).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let synced = Status::Synced;
+        assert_eq!(exit_status([]), EXIT_UNCHANGED);
+        assert_eq!(exit_status([synced(Outcome::Unchanged)]), EXIT_UNCHANGED);
+        assert_eq!(exit_status([synced(Outcome::Skipped)]), EXIT_UNCHANGED);
+        assert_eq!(exit_status([synced(Outcome::Unchanged), synced(Outcome::Created)]), EXIT_UPDATED);
+        assert_eq!(exit_status([synced(Outcome::Updated)]), EXIT_UPDATED);
+        assert_eq!(exit_status([synced(Outcome::Deleted)]), EXIT_UPDATED);
+        assert_eq!(exit_status([synced(Outcome::Updated), Status::Failed]), EXIT_FAILED);
+        assert_eq!(exit_status([synced(Outcome::Unchanged), Status::Idle]), EXIT_FAILED);
+    }
+
+    #[test]
+    fn test_effective_redacted() -> Result<()> {
+        let config = corn::from_str::<Config>(r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "porkbun"
+                      key = "a_key"
+                      secret = "a_secret"
+                    }
+                    domain = "example.com"
+                    hosts = [ "www" { name = "vpn" iface = "wg0" } ]
+                }
+            } "#)?;
+
+        let json = serde_json::to_value(Effective::new(&config))?;
+        assert_eq!(json["provider"]["name"], "porkbun");
+        assert_eq!(json["provider"]["credentials"], "***");
+        assert_eq!(json["hosts"][1]["name"], "vpn");
+        assert_eq!(json["hosts"][1]["iface"], "wg0");
+
+        let dump = json.to_string();
+        assert!(!dump.contains("a_key"));
+        assert!(!dump.contains("a_secret"));
+
+        let debug = format!("{config:?}");
+        assert!(!debug.contains("a_key"));
+        assert!(!debug.contains("a_secret"));
+        Ok(())
+    }
+}
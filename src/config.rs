@@ -14,15 +14,95 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::fs::read_to_string;
+use std::{
+    fs::read_to_string,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
-use once_cell::sync::OnceCell;
+use anyhow::{bail, Context, Result};
+use ipnet::IpNet;
 use pico_args::Arguments;
-use serde::Deserialize;
+use rtnetlink::packet_route::{address::AddressScope, AddressFamily};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 
 use zone_update::Provider;
 
+use crate::{
+    filter::{classify, AddrClass},
+    netlink::IfAddr,
+    pattern::IfacePattern,
+    secrets::{self, Secret},
+};
+
+
+/// The subcommand to run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Monitor the interfaces and keep the records up to date
+    #[default]
+    Run,
+    /// Validate the config, and optionally the provider credentials, then exit
+    Check { online: bool },
+    /// Sync the records with the current addresses once, then exit
+    SyncOnce,
+    /// Print the published records of the configured hosts, or just `host`
+    Get { host: Option<String> },
+    /// Point the records of the configured hosts, or just `host`, at `ip`
+    Set { host: Option<String>, ip: IpAddr },
+    /// Delete the records of the configured hosts, or just `host`
+    Delete { host: Option<String> },
+    /// Print the effective config with secrets redacted
+    PrintConfig,
+}
+
+/// The supported config file formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Corn,
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "corn" => Ok(Format::Corn),
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => bail!("Unknown config format `{s}`"),
+        }
+    }
+}
+
+impl Format {
+    /// Guess the format from the file extension, defaulting to Corn.
+    pub fn from_path(path: &str) -> Format {
+        Path::new(path).extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.parse().ok())
+            .unwrap_or(Format::Corn)
+    }
+
+    pub fn parse(self, conf_s: &str) -> Result<Config> {
+        let conf = match self {
+            Format::Corn => corn::from_str(conf_s)?,
+            Format::Toml => toml::from_str(conf_s)?,
+            Format::Json => serde_json::from_str(conf_s)?,
+            // serde_norway expects enum variants as YAML tags (`!cidr ...`);
+            // going via a JSON value allows the `{ cidr: ... }` form used
+            // by the other formats.
+            Format::Yaml => serde_json::from_value(serde_norway::from_str(conf_s)?)?,
+        };
+        Ok(conf)
+    }
+}
 
 #[derive(Debug)]
 pub struct CliOptions {
@@ -30,55 +110,491 @@ pub struct CliOptions {
     ///
     /// Override the config file location
     pub config: Option<String>,
+    /// Config format
+    ///
+    /// Override the format guessed from the config file extension
+    pub format: Option<Format>,
+    pub command: Command,
 }
 
 impl CliOptions {
     pub fn from_args() -> Result<CliOptions> {
-        let mut args = Arguments::from_env();
+        Self::parse(Arguments::from_env())
+    }
+
+    fn parse(mut args: Arguments) -> Result<CliOptions> {
+        let config = args.opt_value_from_str(["-c", "--config"])?;
+        let format = args.opt_value_from_str(["-f", "--format"])?;
+
+        let command = match args.subcommand()?.as_deref() {
+            None | Some("run") => Command::Run,
+            Some("check") => Command::Check { online: args.contains("--online") },
+            Some("sync") if args.contains("--once") => Command::SyncOnce,
+            Some("sync") => bail!("`sync` requires `--once`"),
+            Some("get") => Command::Get { host: args.opt_value_from_str("--host")? },
+            Some("set") => Command::Set {
+                host: args.opt_value_from_str("--host")?,
+                ip: args.free_from_str().context("`set` requires an IP address")?,
+            },
+            Some("delete") => Command::Delete { host: args.opt_value_from_str("--host")? },
+            Some("print-config") => Command::PrintConfig,
+            Some(cmd) => bail!("Unknown command `{cmd}`"),
+        };
 
-        Ok(CliOptions {
-            config: args.opt_value_from_str(["-c", "--config"])?
-        })
+        let rest = args.finish();
+        if !rest.is_empty() {
+            bail!("Unexpected arguments: {rest:?}");
+        }
 
+        Ok(CliOptions { config, format, command })
     }
 }
 
 
-// FIXME: Use OnceCell lib for now until OnceLock::get_or_try_init()
-// stablises.
-static CONFIG: OnceCell<Config> = OnceCell::new();
-
 pub const DEFAULT_CONFIG_FILE: &str = "/etc/netlink-ddns/config.corn";
 
 
-#[derive(Debug, Deserialize)]
+/// Which address records to publish.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    /// A records only
+    #[default]
+    V4,
+    /// AAAA records only
+    V6,
+    /// Both A and AAAA records
+    Both,
+}
+
+impl IpVersion {
+    /// The netlink address families covered by this setting.
+    pub fn families(&self) -> Vec<AddressFamily> {
+        match self {
+            IpVersion::V4 => vec![AddressFamily::Inet],
+            IpVersion::V6 => vec![AddressFamily::Inet6],
+            IpVersion::Both => vec![AddressFamily::Inet, AddressFamily::Inet6],
+        }
+    }
+}
+
+/// Policy for choosing which address to publish when an interface has
+/// several.
+///
+/// The same rule is applied to the startup query, where the first matching
+/// address is chosen, and to the monitoring stream, where non-matching
+/// addresses are ignored.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddrSelect {
+    /// The primary address; secondary (alias) addresses are skipped.
+    #[default]
+    Primary,
+    /// The first global-scope address.
+    Global,
+    /// An address within the given network.
+    Cidr(IpNet),
+    /// An address with the given label (IPv4 only), e.g. "eth0:1".
+    Label(String),
+    /// Every address, each published as its own record; see
+    /// `HostConfig::slot()`.
+    All,
+}
+
+impl AddrSelect {
+    /// Whether `addr` is acceptable under this policy.
+    pub fn matches(&self, addr: &IfAddr) -> bool {
+        match self {
+            AddrSelect::Primary => !addr.is_secondary(),
+            AddrSelect::Global => addr.scope == AddressScope::Universe,
+            AddrSelect::Cidr(net) => net.contains(&addr.ip),
+            AddrSelect::Label(label) => addr.label.as_ref() == Some(label),
+            AddrSelect::All => true,
+        }
+    }
+
+    /// Choose the first acceptable address.
+    pub fn select<'a>(&self, addrs: impl IntoIterator<Item = &'a IfAddr>) -> Option<&'a IfAddr> {
+        addrs.into_iter()
+            .find(|addr| self.matches(addr))
+    }
+}
+
+/// Where the address to publish is obtained from.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddrSource {
+    /// The interface address reported by netlink.
+    #[default]
+    Netlink,
+    /// A "what is my IP" HTTP(S) endpoint returning the address as plain text.
+    Http(String),
+    /// A STUN server, as `host:port`.
+    Stun(String),
+}
+
+/// What to do with addresses that aren't publicly routable.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NonGlobal {
+    /// Don't publish the address.
+    #[default]
+    Skip,
+    /// Publish the address anyway, but log a warning.
+    Warn,
+    /// Publish the address reported by this discovery source instead.
+    Fallback(AddrSource),
+}
+
+/// Guards against publishing private, CGNAT, link-local and other
+/// non-global addresses. `allow` and `deny` take precedence over the
+/// built-in classification.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Filter {
+    pub non_global: NonGlobal,
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl Filter {
+    /// Whether `ip` may be published as-is.
+    pub fn is_publishable(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|n| n.contains(ip)) {
+            false
+        } else if self.allow.iter().any(|n| n.contains(ip)) {
+            true
+        } else {
+            classify(ip) == AddrClass::Global
+        }
+    }
+}
+
+fn default_grace() -> u64 {
+    300
+}
+
+/// What to do when the published address is removed from the interface.
+///
+/// The action is taken after `grace` seconds, and is cancelled if a
+/// replacement address appears in the meantime.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", tag = "action")]
+pub enum OnDelete {
+    /// Leave the record as-is.
+    #[default]
+    Ignore,
+    /// Delete the record.
+    Delete {
+        #[serde(default = "default_grace")]
+        grace: u64,
+    },
+    /// Point the record at a fallback address of the same family.
+    Fallback {
+        addresses: Vec<IpAddr>,
+        #[serde(default = "default_grace")]
+        grace: u64,
+    },
+}
+
+impl OnDelete {
+    /// The grace period before acting, if any action is configured.
+    pub fn grace(&self) -> Option<Duration> {
+        match self {
+            OnDelete::Ignore => None,
+            OnDelete::Delete { grace } | OnDelete::Fallback { grace, .. } => Some(Duration::from_secs(*grace)),
+        }
+    }
+}
+
+/// A host entry; either a bare hostname or a table with per-host overrides.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum HostEntry {
+    Name(String),
+    Host(Host),
+}
+
+/// Per-host settings. Unset fields fall back to the values in `Ddns`, and
+/// `iface` to the top-level `Config::iface`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub struct Host {
+    pub name: String,
+    pub domain: Option<String>,
+    pub iface: Option<String>,
+    pub ip_version: Option<IpVersion>,
+    pub select: Option<AddrSelect>,
+    pub source: Option<AddrSource>,
+    pub on_delete: Option<OnDelete>,
+}
+
+/// The effective settings for a single host after applying overrides.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HostConfig {
+    pub name: String,
+    pub domain: String,
+    pub iface: String,
+    pub ip_version: IpVersion,
+    pub select: AddrSelect,
+    pub source: AddrSource,
+    pub on_delete: OnDelete,
+}
+
+impl HostConfig {
+    /// The fully-qualified name of the host; e.g. "www.example.com".
+    pub fn fqdn(&self) -> String {
+        format!("{}.{}", self.name, self.domain)
+    }
+
+    /// The host for the `n`th record of a host publishing all its addresses.
+    ///
+    /// The first record uses the host's own name and the others a numbered
+    /// one; e.g. "www", "www-2", "www-3", or "vpn.home", "vpn-2.home".
+    pub fn slot(&self, n: usize) -> HostConfig {
+        if n == 0 {
+            return self.clone();
+        }
+        let name = match self.name.split_once('.') {
+            Some((first, rest)) => format!("{first}-{}.{rest}", n + 1),
+            None => format!("{}-{}", self.name, n + 1),
+        };
+        HostConfig { name, ..self.clone() }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub struct Ddns {
     pub domain: String,
-    pub host: String,
-    pub provider: Provider,
+    pub host: Option<String>,
+    #[serde(default)]
+    pub hosts: Vec<HostEntry>,
+    /// Secret values may be references; see `secrets::resolve()`.
+    #[serde(deserialize_with = "deserialize_provider")]
+    pub provider: Secret<Provider>,
+    #[serde(default)]
+    pub ip_version: IpVersion,
+    #[serde(default)]
+    pub select: AddrSelect,
+    #[serde(default)]
+    pub source: AddrSource,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub on_delete: OnDelete,
+}
+
+fn unique_families(all: impl Iterator<Item = AddressFamily>) -> Vec<AddressFamily> {
+    let mut families = Vec::new();
+    for family in all {
+        if !families.contains(&family) {
+            families.push(family);
+        }
+    }
+    families
+}
+
+/// Backoff settings for retrying failed provider updates; delays are in seconds.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Retry {
+    pub initial_delay: u64,
+    pub max_delay: u64,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            initial_delay: 5,
+            max_delay: 600,
+        }
+    }
+}
+
+/// Settings for the Prometheus metrics endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Metrics {
+    /// The address to serve `/metrics` on, e.g. "127.0.0.1:9464".
+    pub listen: SocketAddr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub log_level: Option<String>,
-    pub iface: String,
+    /// The default interface for hosts that don't specify one; "auto"
+    /// follows the default route. This may be a pattern; see `IfacePattern`.
+    pub iface: Option<String>,
     pub ddns: Ddns,
     #[serde(default)]
     pub dry_run: bool,
+    #[serde(default)]
+    pub retry: Retry,
+    /// How often to re-read the published records and correct any drift,
+    /// in seconds; disabled if unset.
+    pub reconcile_interval: Option<u64>,
+    /// Where to persist the last published state of each record, e.g.
+    /// "/var/lib/netlink-ddns/state.json"; disabled if unset.
+    pub state_file: Option<String>,
+    /// Serve Prometheus metrics over HTTP; disabled if unset.
+    pub metrics: Option<Metrics>,
 }
 
-pub fn get_config(cli_file: &Option<String>) -> Result<&'static Config> {
-    CONFIG.get_or_try_init(|| {
+impl Config {
+    /// Returns all configured hosts with defaults applied.
+    ///
+    /// Hosts without an interface (and no top-level default) are returned
+    /// with an empty `iface`; `validate()` rejects these.
+    pub fn hosts(&self) -> Vec<HostConfig> {
+        let ddns = &self.ddns;
+        let default_iface = self.iface.clone().unwrap_or_default();
+        let single = ddns.host.iter()
+            .map(|name| HostConfig {
+                name: name.clone(),
+                domain: ddns.domain.clone(),
+                iface: default_iface.clone(),
+                ip_version: ddns.ip_version,
+                select: ddns.select.clone(),
+                source: ddns.source.clone(),
+                on_delete: ddns.on_delete.clone(),
+            });
+        let listed = ddns.hosts.iter()
+            .map(|entry| match entry {
+                HostEntry::Name(name) => HostConfig {
+                    name: name.clone(),
+                    domain: ddns.domain.clone(),
+                    iface: default_iface.clone(),
+                    ip_version: ddns.ip_version,
+                    select: ddns.select.clone(),
+                    source: ddns.source.clone(),
+                    on_delete: ddns.on_delete.clone(),
+                },
+                HostEntry::Host(host) => HostConfig {
+                    name: host.name.clone(),
+                    domain: host.domain.clone().unwrap_or_else(|| ddns.domain.clone()),
+                    iface: host.iface.clone().unwrap_or_else(|| default_iface.clone()),
+                    ip_version: host.ip_version.unwrap_or(ddns.ip_version),
+                    select: host.select.clone().unwrap_or_else(|| ddns.select.clone()),
+                    source: host.source.clone().unwrap_or_else(|| ddns.source.clone()),
+                    on_delete: host.on_delete.clone().unwrap_or_else(|| ddns.on_delete.clone()),
+                },
+            });
+        single.chain(listed).collect()
+    }
 
-        let confile = cli_file.clone()
-            .unwrap_or(DEFAULT_CONFIG_FILE.to_owned());
-        let conf_s = read_to_string(&confile)
-            .with_context(|| format!("Failed to load config from {confile}"))?;
+    /// The distinct interfaces used by all hosts, in configuration order.
+    pub fn interfaces(&self) -> Vec<String> {
+        let mut ifaces = Vec::new();
+        for host in self.hosts() {
+            if !ifaces.contains(&host.iface) {
+                ifaces.push(host.iface);
+            }
+        }
+        ifaces
+    }
 
-        let conf = corn::from_str::<Config>(&conf_s)?;
-        Ok(conf)
-    })
+    /// The union of address families needed by the hosts on `iface`.
+    pub fn families(&self, iface: &str) -> Vec<AddressFamily> {
+        unique_families(self.hosts().iter()
+            .filter(|h| h.iface == iface)
+            .flat_map(|h| h.ip_version.families()))
+    }
+
+    /// The union of address families needed by all hosts.
+    pub fn all_families(&self) -> Vec<AddressFamily> {
+        unique_families(self.hosts().iter()
+            .flat_map(|h| h.ip_version.families()))
+    }
+
+    fn validate(&self) -> Result<()> {
+        let hosts = self.hosts();
+        if hosts.is_empty() {
+            bail!("No hosts configured; set `host` or `hosts` in `ddns`");
+        }
+        if self.ddns.filter.non_global == NonGlobal::Fallback(AddrSource::Netlink) {
+            bail!("The non-global fallback must be an external source");
+        }
+        if self.reconcile_interval == Some(0) {
+            bail!("`reconcile_interval` must be non-zero");
+        }
+        if self.retry.initial_delay == 0 || self.retry.max_delay < self.retry.initial_delay {
+            bail!("Invalid retry settings; `initial_delay` must be non-zero and no greater than `max_delay`");
+        }
+        for (i, host) in hosts.iter().enumerate() {
+            if !is_dns_name(&host.domain, false) {
+                bail!("Invalid domain `{}`", host.domain);
+            }
+            if !is_dns_name(&host.name, true) {
+                bail!("Invalid host name `{}` in {}", host.name, host.domain);
+            }
+            if host.iface.is_empty() {
+                bail!("No interface configured for host {}; set `iface` globally or on the host", host.name);
+            }
+            let pattern = IfacePattern::parse(&host.iface)?;
+            if pattern.is_none() && let Some((iface, _)) = host.iface.split_once(':') {
+                bail!("Interface `{}` for host {} is an alias; set `iface = \"{iface}\"` and `select = {{ label = \"{}\" }}`",
+                      host.iface, host.name, host.iface);
+            }
+            if host.select == AddrSelect::All {
+                if host.source != AddrSource::Netlink {
+                    bail!("Host {} can't use `select = \"all\"` with an external address source", host.name);
+                }
+                if host.name.starts_with('*') {
+                    bail!("Wildcard host {} can't use `select = \"all\"`", host.name);
+                }
+            }
+            if hosts[..i].iter().any(|h| h.name == host.name && h.domain == host.domain) {
+                bail!("Host {} is configured more than once", host.fqdn());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn deserialize_provider<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Secret<Provider>, D::Error> {
+    let mut value = serde_json::Value::deserialize(deserializer)?;
+    secrets::resolve_all(&mut value)
+        .map_err(|e| D::Error::custom(format!("{e:#}")))?;
+    serde_json::from_value(value)
+        .map(Secret::new)
+        .map_err(D::Error::custom)
+}
+
+/// Check that `name` is a syntactically valid DNS name. Labels may contain
+/// letters, digits, hyphens and underscores, and `*` is allowed as the
+/// leftmost label if `wildcard` is set.
+fn is_dns_name(name: &str, wildcard: bool) -> bool {
+    let valid_label = |label: &str| {
+        (1..=63).contains(&label.len())
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let mut labels = name.split('.');
+    let first = labels.next().unwrap_or_default();
+
+    name.len() <= 253
+        && (valid_label(first) || (wildcard && first == "*"))
+        && labels.all(valid_label)
+}
+
+/// Load and validate the config file; this is called again on reload, so
+/// must not have side effects.
+///
+/// The format is taken from the file extension unless `format` is given.
+pub fn get_config(cli_file: &Option<String>, format: Option<Format>) -> Result<Config> {
+    let confile = cli_file.clone()
+        .unwrap_or(DEFAULT_CONFIG_FILE.to_owned());
+    let conf_s = read_to_string(&confile)
+        .with_context(|| format!("Failed to load config from {confile}"))?;
+
+    let format = format.unwrap_or_else(|| Format::from_path(&confile));
+    let conf = format.parse(&conf_s)
+        .with_context(|| format!("Failed to parse {format:?} config {confile}"))?;
+    conf.validate()?;
+    Ok(conf)
 }
 
 
@@ -107,9 +623,9 @@ mod tests {
                 }
             } "#;
         let conf = corn::from_str::<ConfWrapper>(fragment)?;
-        assert_eq!(conf.ddns.host, "test".to_string());
+        assert_eq!(conf.ddns.host, Some("test".to_string()));
         assert_eq!(conf.ddns.domain, "example.com".to_string());
-        if let Provider::PorkBun(auth) = conf.ddns.provider {
+        if let Provider::PorkBun(auth) = &*conf.ddns.provider {
             assert_eq!(auth.key, "a_key".to_string());
             assert_eq!(auth.secret, "a_secret".to_string());
         } else {
@@ -135,10 +651,10 @@ mod tests {
                 }
             } "#;
         let conf = corn::from_str::<ConfWrapper>(fragment)?;
-        assert_eq!(conf.ddns.host, "test".to_string());
+        assert_eq!(conf.ddns.host, Some("test".to_string()));
         assert_eq!(conf.ddns.domain, "example.com".to_string());
-        if let Provider::Gandi(gandi::Auth::ApiKey(key)) = conf.ddns.provider {
-            assert_eq!(key, "api_key".to_string());
+        if let Provider::Gandi(gandi::Auth::ApiKey(key)) = &*conf.ddns.provider {
+            assert_eq!(key, "api_key");
         } else {
             panic!("Provider mismatch, should be PorkBun");
         }
@@ -167,17 +683,883 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_provider_secret_refs() {
+        let fragment = r#"
+            {
+                ddns = {
+                    provider = {
+                      name = "porkbun"
+                      key = "env:NETLINK_DDNS_TEST_PORKBUN_KEY"
+                      secret = "a_secret"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+
+        temp_env::with_var("NETLINK_DDNS_TEST_PORKBUN_KEY", Some("a_key"), || {
+            let conf = corn::from_str::<ConfWrapper>(fragment).unwrap();
+            if let Provider::PorkBun(auth) = &*conf.ddns.provider {
+                assert_eq!(auth.key, "a_key".to_string());
+                assert_eq!(auth.secret, "a_secret".to_string());
+            } else {
+                panic!("Provider mismatch, should be PorkBun");
+            }
+        });
+
+        temp_env::with_var_unset("NETLINK_DDNS_TEST_PORKBUN_KEY", || {
+            let err = corn::from_str::<ConfWrapper>(fragment).unwrap_err();
+            assert!(err.to_string().contains("NETLINK_DDNS_TEST_PORKBUN_KEY"), "{err}");
+        });
+    }
+
+    #[test]
+    fn test_ip_version() -> Result<()> {
+        let fragment = r#"
+            {
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                    ip_version = "both"
+                }
+            } "#;
+        let conf = corn::from_str::<ConfWrapper>(fragment)?;
+        assert_eq!(conf.ddns.ip_version, IpVersion::Both);
+        assert_eq!(conf.ddns.ip_version.families(),
+                   vec![AddressFamily::Inet, AddressFamily::Inet6]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_hosts() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    ip_version = "v6"
+                    hosts = [
+                        "test"
+                        { name = "other" ip_version = "both" }
+                        { name = "www" domain = "example.net" }
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        let hosts = conf.hosts();
+        assert_eq!(hosts, vec![
+            HostConfig {
+                name: "test".to_string(),
+                domain: "example.com".to_string(),
+                iface: "eth0".to_string(),
+                ip_version: IpVersion::V6,
+                select: AddrSelect::Primary,
+                source: AddrSource::Netlink,
+                on_delete: OnDelete::Ignore,
+            },
+            HostConfig {
+                name: "other".to_string(),
+                domain: "example.com".to_string(),
+                iface: "eth0".to_string(),
+                ip_version: IpVersion::Both,
+                select: AddrSelect::Primary,
+                source: AddrSource::Netlink,
+                on_delete: OnDelete::Ignore,
+            },
+            HostConfig {
+                name: "www".to_string(),
+                domain: "example.net".to_string(),
+                iface: "eth0".to_string(),
+                ip_version: IpVersion::V6,
+                select: AddrSelect::Primary,
+                source: AddrSource::Netlink,
+                on_delete: OnDelete::Ignore,
+            },
+        ]);
+        assert_eq!(conf.families("eth0"),
+                   vec![AddressFamily::Inet6, AddressFamily::Inet]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_interfaces() -> Result<()> {
+        let fragment = r#"
+            {
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    hosts = [
+                        { name = "wan0" iface = "wan0" }
+                        { name = "wan1" iface = "wan1" ip_version = "v6" }
+                        { name = "vpn" iface = "wg0" }
+                        { name = "wan" iface = "wan0" }
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        assert_eq!(conf.interfaces(), vec!["wan0", "wan1", "wg0"]);
+        assert_eq!(conf.families("wan1"), vec![AddressFamily::Inet6]);
+        assert_eq!(conf.all_families(), vec![AddressFamily::Inet, AddressFamily::Inet6]);
+        assert_eq!(conf.hosts().iter()
+                       .filter(|h| h.iface == "wan0")
+                       .map(|h| h.name.as_str())
+                       .collect::<Vec<_>>(),
+                   vec!["wan0", "wan"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_iface() -> Result<()> {
+        let fragment = r#"
+            {
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    hosts = [
+                        { name = "wan0" iface = "wan0" }
+                        "test"
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_policy() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    select = "global"
+                    hosts = [
+                        "test"
+                        { name = "lan" select = { cidr = "10.0.0.0/8" } }
+                        { name = "alias" select = { label = "eth0:1" } }
+                        { name = "all" select = "all" }
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        let selects = conf.hosts().into_iter()
+            .map(|h| h.select)
+            .collect::<Vec<_>>();
+        assert_eq!(selects, vec![
+            AddrSelect::Global,
+            AddrSelect::Cidr("10.0.0.0/8".parse()?),
+            AddrSelect::Label("eth0:1".to_string()),
+            AddrSelect::All,
+        ]);
+        conf.validate()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_all() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    select = "all"
+                    hosts = [ "www" "vpn.home" ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        let hosts = conf.hosts();
+        assert_eq!(hosts[0].slot(0), hosts[0]);
+        assert_eq!(hosts[0].slot(1).fqdn(), "www-2.example.com");
+        assert_eq!(hosts[1].slot(2).fqdn(), "vpn-3.home.example.com");
+
+        let conf = corn::from_str::<Config>(&fragment.replace(r#""www""#, r#""*""#))?;
+        assert!(conf.validate().is_err());
+        let conf = corn::from_str::<Config>(&fragment.replace(r#"select = "all""#,
+            r#"select = "all" source = { http = "https://api.ipify.org" }"#))?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_addr_source() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    source = { http = "https://api.ipify.org" }
+                    hosts = [
+                        "test"
+                        { name = "stun" source = { stun = "stun.example.com:3478" } }
+                        { name = "local" source = "netlink" }
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        let sources = conf.hosts().into_iter()
+            .map(|h| h.source)
+            .collect::<Vec<_>>();
+        assert_eq!(sources, vec![
+            AddrSource::Http("https://api.ipify.org".to_string()),
+            AddrSource::Stun("stun.example.com:3478".to_string()),
+            AddrSource::Netlink,
+        ]);
+
+        Ok(())
+    }
+
+    fn ifaddr(ip: &str, label: &str, scope: AddressScope, secondary: bool) -> IfAddr {
+        use rtnetlink::packet_route::address::AddressFlags;
+        IfAddr {
+            ip: ip.parse().unwrap(),
+            peer: None,
+            prefix_len: 24,
+            label: Some(label.to_string()),
+            scope,
+            flags: if secondary { AddressFlags::Secondary } else { AddressFlags::Permanent },
+        }
+    }
+
+    #[test]
+    fn test_select_addresses() -> Result<()> {
+        let addrs = vec![
+            ifaddr("127.0.0.2", "eth0", AddressScope::Host, false),
+            ifaddr("10.1.1.1", "eth0", AddressScope::Universe, false),
+            ifaddr("192.168.1.1", "eth0:1", AddressScope::Universe, true),
+        ];
+
+        let select = |policy: AddrSelect| policy.select(&addrs).map(|a| a.ip.to_string());
+
+        assert_eq!(select(AddrSelect::Primary), Some("127.0.0.2".to_string()));
+        assert_eq!(select(AddrSelect::Global), Some("10.1.1.1".to_string()));
+        assert_eq!(select(AddrSelect::Cidr("192.168.0.0/16".parse()?)), Some("192.168.1.1".to_string()));
+        assert_eq!(select(AddrSelect::Label("eth0:1".to_string())), Some("192.168.1.1".to_string()));
+        assert_eq!(select(AddrSelect::Label("eth0:2".to_string())), None);
+        assert_eq!(select(AddrSelect::Cidr("172.16.0.0/12".parse()?)), None);
+        assert!(addrs.iter().all(|a| AddrSelect::All.matches(a)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                    filter = {
+                        non_global = { fallback = { http = "https://api.ipify.org" } }
+                        allow = [ "10.1.0.0/16" ]
+                        deny = [ "8.8.8.0/24" ]
+                    }
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        let filter = &conf.ddns.filter;
+        assert_eq!(filter.non_global,
+                   NonGlobal::Fallback(AddrSource::Http("https://api.ipify.org".to_string())));
+
+        assert!(filter.is_publishable(&"1.1.1.1".parse()?));
+        assert!(filter.is_publishable(&"10.1.2.3".parse()?));
+        assert!(!filter.is_publishable(&"10.2.2.3".parse()?));
+        assert!(!filter.is_publishable(&"100.64.1.1".parse()?));
+        assert!(!filter.is_publishable(&"8.8.8.8".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_default() -> Result<()> {
+        let filter = Filter::default();
+        assert_eq!(filter.non_global, NonGlobal::Skip);
+        assert!(filter.is_publishable(&"2606:4700::1111".parse()?));
+        assert!(!filter.is_publishable(&"192.168.1.1".parse()?));
+        assert!(!filter.is_publishable(&"fe80::1".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_delete() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    on_delete = { action = "delete" }
+                    hosts = [
+                        "test"
+                        { name = "ignored" on_delete = { action = "ignore" } }
+                        { name = "fallback"
+                          on_delete = { action = "fallback" addresses = [ "192.0.2.1" ] grace = 30 } }
+                    ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        let policies = conf.hosts().into_iter()
+            .map(|h| h.on_delete)
+            .collect::<Vec<_>>();
+        assert_eq!(policies, vec![
+            OnDelete::Delete { grace: 300 },
+            OnDelete::Ignore,
+            OnDelete::Fallback { addresses: vec!["192.0.2.1".parse()?], grace: 30 },
+        ]);
+        assert_eq!(policies[0].grace(), Some(Duration::from_secs(300)));
+        assert_eq!(policies[1].grace(), None);
+        assert_eq!(policies[2].grace(), Some(Duration::from_secs(30)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retry() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                retry = { initial_delay = 1 }
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        assert_eq!(conf.retry, Retry { initial_delay: 1, max_delay: 600 });
+
+        let fragment = r#"
+            {
+                iface = "eth0"
+                retry = { initial_delay = 60 max_delay = 10 }
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconcile_interval() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                reconcile_interval = 3600
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        conf.validate()?;
+        assert_eq!(conf.reconcile_interval, Some(3600));
+
+        let conf = corn::from_str::<Config>(&fragment.replace("3600", "0"))?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_file() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                state_file = "/var/lib/netlink-ddns/state.json"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert_eq!(conf.state_file, Some("/var/lib/netlink-ddns/state.json".to_string()));
+
+        let conf = corn::from_str::<Config>(&fragment.replace(r#"state_file = "/var/lib/netlink-ddns/state.json""#, ""))?;
+        assert_eq!(conf.state_file, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_metrics() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                metrics = { listen = "127.0.0.1:9464" }
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert_eq!(conf.metrics, Some(Metrics { listen: "127.0.0.1:9464".parse()? }));
+
+        let conf = corn::from_str::<Config>(&fragment.replace("127.0.0.1:9464", "localhost"));
+        assert!(conf.is_err());
+
+        let conf = corn::from_str::<Config>(&fragment.replace(r#"metrics = { listen = "127.0.0.1:9464" }"#, ""))?;
+        assert_eq!(conf.metrics, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_hosts() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dns_names() {
+        assert!(is_dns_name("example.com", false));
+        assert!(is_dns_name("example.com.", false));
+        assert!(is_dns_name("www", true));
+        assert!(is_dns_name("_acme-challenge.www", true));
+        assert!(is_dns_name("*.dev", true));
+        assert!(!is_dns_name("*.example.com", false));
+        assert!(!is_dns_name("", true));
+        assert!(!is_dns_name("www..dev", true));
+        assert!(!is_dns_name("-www", true));
+        assert!(!is_dns_name("www-", true));
+        assert!(!is_dns_name("w w", true));
+        assert!(!is_dns_name("dev.*", true));
+        assert!(!is_dns_name(&"a".repeat(64), true));
+    }
+
+    #[test]
+    fn test_invalid_host_name() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(&fragment.replace(r#"host = "test""#, r#"host = "te st""#))?;
+        assert!(conf.validate().is_err());
+
+        let conf = corn::from_str::<Config>(&fragment.replace("example.com", "example..com"))?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_alias_iface() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0:1"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains(r#"select = { label = "eth0:1" }"#), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iface_patterns() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "IFACE"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                }
+            } "#;
+        for iface in ["ppp*", "wg-site-[0-9]", r"/^eth\\d:\\d$/"] {
+            let conf = corn::from_str::<Config>(&fragment.replace("IFACE", iface))?;
+            conf.validate()?;
+        }
+
+        let conf = corn::from_str::<Config>(&fragment.replace("IFACE", "/ppp(/"))?;
+        let err = conf.validate().unwrap_err().to_string();
+        assert!(err.contains("Invalid interface pattern"), "{err}");
+
+        Ok(())
+    }
+
+    fn parse_args(args: &[&str]) -> Result<CliOptions> {
+        CliOptions::parse(Arguments::from_vec(args.iter().map(Into::into).collect()))
+    }
+
+    #[test]
+    fn test_cli_commands() -> Result<()> {
+        let cli = parse_args(&[])?;
+        assert_eq!(cli.config, None);
+        assert_eq!(cli.command, Command::Run);
+
+        let cli = parse_args(&["-c", "test.corn", "check"])?;
+        assert_eq!(cli.config, Some("test.corn".to_string()));
+        assert_eq!(cli.command, Command::Check { online: false });
+
+        let cli = parse_args(&["-f", "yaml", "check", "--online", "--config", "test.corn"])?;
+        assert_eq!(cli.format, Some(Format::Yaml));
+        assert_eq!(cli.config, Some("test.corn".to_string()));
+        assert_eq!(cli.command, Command::Check { online: true });
+
+        let cli = parse_args(&["sync", "--once"])?;
+        assert_eq!(cli.command, Command::SyncOnce);
+        assert!(parse_args(&["sync"]).is_err());
+
+        let cli = parse_args(&["get"])?;
+        assert_eq!(cli.command, Command::Get { host: None });
+
+        let cli = parse_args(&["set", "--host", "www", "192.0.2.1"])?;
+        assert_eq!(cli.command, Command::Set { host: Some("www".to_string()), ip: "192.0.2.1".parse()? });
+        assert!(parse_args(&["set"]).is_err());
+        assert!(parse_args(&["set", "not-an-ip"]).is_err());
+
+        let cli = parse_args(&["delete", "--host", "www.example.com"])?;
+        assert_eq!(cli.command, Command::Delete { host: Some("www.example.com".to_string()) });
+
+        let cli = parse_args(&["print-config"])?;
+        assert_eq!(cli.command, Command::PrintConfig);
+
+        assert!(parse_args(&["frobnicate"]).is_err());
+        assert!(parse_args(&["check", "--offline"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_hosts() -> Result<()> {
+        let fragment = r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    host = "test"
+                    hosts = [ "test" ]
+                }
+            } "#;
+        let conf = corn::from_str::<Config>(fragment)?;
+        assert!(conf.validate().is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_example_config() -> Result<()> {
-        let file = "examples/config.corn".to_owned();
-        let conf = get_config(&Some(file))?;
+        for ext in ["corn", "toml", "json", "yaml"] {
+            let file = format!("examples/config.{ext}");
+            let conf = get_config(&Some(file), None)?;
 
-        assert_eq!(conf.ddns.host, "test".to_string());
-        assert_eq!(conf.ddns.domain, "example.com".to_string());
-        if let Provider::DigitalOcean(auth) = &conf.ddns.provider {
-            assert_eq!(auth.key, "a_key".to_string());
-        } else {
-            panic!("Provider mismatch");
+            assert_eq!(conf.log_level, Some("debug".to_string()));
+            assert_eq!(conf.iface, Some("test0".to_string()));
+            assert_eq!(conf.ddns.host, Some("test".to_string()));
+            assert_eq!(conf.ddns.domain, "example.com".to_string());
+            assert_eq!(conf.ddns.ip_version, IpVersion::V4);
+            if let Provider::DigitalOcean(auth) = &*conf.ddns.provider {
+                assert_eq!(auth.key, "a_key".to_string());
+            } else {
+                panic!("Provider mismatch");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("/etc/netlink-ddns/config.corn"), Format::Corn);
+        assert_eq!(Format::from_path("config.toml"), Format::Toml);
+        assert_eq!(Format::from_path("config.json"), Format::Json);
+        assert_eq!(Format::from_path("config.yaml"), Format::Yaml);
+        assert_eq!(Format::from_path("config.YML"), Format::Yaml);
+        assert_eq!(Format::from_path("config"), Format::Corn);
+        assert!("ini".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_explicit_format() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("config.conf");
+        std::fs::copy("examples/config.json", &file)?;
+        let path = Some(file.to_string_lossy().to_string());
+
+        assert!(get_config(&path, None).is_err());
+        let conf = get_config(&path, Some(Format::Json))?;
+        assert_eq!(conf.ddns.host, Some("test".to_string()));
+        Ok(())
+    }
+
+    const PORKBUN_TOML: &str = r#"
+        [ddns]
+        domain = "example.com"
+        host = "test"
+        provider = { name = "porkbun", key = "a_key", secret = "a_secret" }
+    "#;
+    const PORKBUN_JSON: &str = r#"
+        { "ddns": {
+            "domain": "example.com",
+            "host": "test",
+            "provider": { "name": "porkbun", "key": "a_key", "secret": "a_secret" }
+        } }
+    "#;
+    const PORKBUN_YAML: &str = r#"
+        ddns:
+          domain: example.com
+          host: test
+          provider: { name: porkbun, key: a_key, secret: a_secret }
+    "#;
+
+    #[test]
+    fn test_tagged_provider_formats() -> Result<()> {
+        let confs: [ConfWrapper; 3] = [
+            toml::from_str(PORKBUN_TOML)?,
+            serde_json::from_str(PORKBUN_JSON)?,
+            serde_json::from_value(serde_norway::from_str(PORKBUN_YAML)?)?,
+        ];
+        for conf in confs {
+            assert_eq!(conf.ddns.host, Some("test".to_string()));
+            assert_eq!(conf.ddns.domain, "example.com".to_string());
+            if let Provider::PorkBun(auth) = &*conf.ddns.provider {
+                assert_eq!(auth.key, "a_key".to_string());
+                assert_eq!(auth.secret, "a_secret".to_string());
+            } else {
+                panic!("Provider mismatch, should be PorkBun");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tagged_gandi_formats() -> Result<()> {
+        use zone_update::gandi;
+
+        let gandi = |conf: &str| conf
+            .replace("porkbun", "gandi")
+            .replace(r#"key = "a_key", secret = "a_secret""#, r#"apikey = "api_key""#)
+            .replace(r#""key": "a_key", "secret": "a_secret""#, r#""apikey": "api_key""#)
+            .replace("key: a_key, secret: a_secret", "apikey: api_key");
+        let confs: [ConfWrapper; 3] = [
+            toml::from_str(&gandi(PORKBUN_TOML))?,
+            serde_json::from_str(&gandi(PORKBUN_JSON))?,
+            serde_json::from_value(serde_norway::from_str(&gandi(PORKBUN_YAML))?)?,
+        ];
+        for conf in confs {
+            if let Provider::Gandi(gandi::Auth::ApiKey(key)) = &*conf.ddns.provider {
+                assert_eq!(key, "api_key");
+            } else {
+                panic!("Provider mismatch, should be Gandi");
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_gandi_mixed_formats() {
+        // Only one variant should be allowed
+        let mixed = |conf: &str| conf
+            .replace("porkbun", "gandi")
+            .replace(r#"key = "a_key", secret = "a_secret""#, r#"apikey = "api_key", patkey = "pat_key""#)
+            .replace(r#""key": "a_key", "secret": "a_secret""#, r#""apikey": "api_key", "patkey": "pat_key""#)
+            .replace("key: a_key, secret: a_secret", "apikey: api_key, patkey: pat_key");
+        assert!(toml::from_str::<ConfWrapper>(&mixed(PORKBUN_TOML)).is_err());
+        assert!(serde_json::from_str::<ConfWrapper>(&mixed(PORKBUN_JSON)).is_err());
+        let yaml = serde_norway::from_str(&mixed(PORKBUN_YAML)).unwrap();
+        assert!(serde_json::from_value::<ConfWrapper>(yaml).is_err());
+    }
+
+    /// Exercises the enum representations used by the config.
+    const FULL_CORN: &str = r#"
+        {
+            iface = "eth0"
+            retry = { initial_delay = 2 max_delay = 60 }
+            reconcile_interval = 3600
+            ddns = {
+                provider = { name = "digitalocean" key = "a_key" }
+                domain = "example.com"
+                ip_version = "both"
+                select = { cidr = "203.0.113.0/24" }
+                source = { stun = "stun.example.net:3478" }
+                filter = { non_global = { fallback = { http = "https://ip.example.net" } } deny = [ "10.0.0.0/8" ] }
+                on_delete = { action = "fallback" addresses = [ "192.0.2.1" ] grace = 60 }
+                hosts = [
+                    "www"
+                    { name = "vpn" iface = "wg0" ip_version = "v4" select = { label = "wg0" } on_delete = { action = "delete" } }
+                ]
+            }
+        } "#;
+
+    #[test]
+    fn test_full_config_formats() -> Result<()> {
+        let toml = r#"
+            iface = "eth0"
+            retry = { initial_delay = 2, max_delay = 60 }
+            reconcile_interval = 3600
+
+            [ddns]
+            provider = { name = "digitalocean", key = "a_key" }
+            domain = "example.com"
+            ip_version = "both"
+            select = { cidr = "203.0.113.0/24" }
+            source = { stun = "stun.example.net:3478" }
+            filter = { non_global = { fallback = { http = "https://ip.example.net" } }, deny = [ "10.0.0.0/8" ] }
+            on_delete = { action = "fallback", addresses = [ "192.0.2.1" ], grace = 60 }
+            hosts = [
+                "www",
+                { name = "vpn", iface = "wg0", ip_version = "v4", select = { label = "wg0" }, on_delete = { action = "delete" } },
+            ]
+        "#;
+        let json = r#"
+            {
+                "iface": "eth0",
+                "retry": { "initial_delay": 2, "max_delay": 60 },
+                "reconcile_interval": 3600,
+                "ddns": {
+                    "provider": { "name": "digitalocean", "key": "a_key" },
+                    "domain": "example.com",
+                    "ip_version": "both",
+                    "select": { "cidr": "203.0.113.0/24" },
+                    "source": { "stun": "stun.example.net:3478" },
+                    "filter": { "non_global": { "fallback": { "http": "https://ip.example.net" } }, "deny": [ "10.0.0.0/8" ] },
+                    "on_delete": { "action": "fallback", "addresses": [ "192.0.2.1" ], "grace": 60 },
+                    "hosts": [
+                        "www",
+                        { "name": "vpn", "iface": "wg0", "ip_version": "v4", "select": { "label": "wg0" }, "on_delete": { "action": "delete" } }
+                    ]
+                }
+            }
+        "#;
+        let yaml = r#"
+            iface: eth0
+            retry: { initial_delay: 2, max_delay: 60 }
+            reconcile_interval: 3600
+            ddns:
+              provider: { name: digitalocean, key: a_key }
+              domain: example.com
+              ip_version: both
+              select: { cidr: 203.0.113.0/24 }
+              source: { stun: "stun.example.net:3478" }
+              filter:
+                non_global: { fallback: { http: "https://ip.example.net" } }
+                deny: [ 10.0.0.0/8 ]
+              on_delete: { action: fallback, addresses: [ 192.0.2.1 ], grace: 60 }
+              hosts:
+                - www
+                - name: vpn
+                  iface: wg0
+                  ip_version: v4
+                  select: { label: wg0 }
+                  on_delete: { action: delete }
+        "#;
+
+        let expected = Format::Corn.parse(FULL_CORN)?;
+        expected.validate()?;
+        for (format, conf_s) in [(Format::Toml, toml), (Format::Json, json), (Format::Yaml, yaml)] {
+            let conf = format.parse(conf_s)?;
+            // `Provider` doesn't implement `PartialEq`
+            assert_eq!(format!("{conf:?}"), format!("{expected:?}"), "{format:?}");
+            assert_eq!(format!("{:?}", *conf.ddns.provider), format!("{:?}", *expected.ddns.provider));
+            assert_eq!(conf.hosts(), expected.hosts(), "{format:?}");
         }
 
         Ok(())
@@ -1,14 +1,168 @@
 
-use zone_update::async_impl::AsyncDnsProvider;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Instant,
+};
 
-use crate::config::Config;
+use anyhow::{bail, Result};
+use rtnetlink::packet_route::AddressFamily;
+use zone_update::{
+    async_impl::{
+        cloudflare::Cloudflare, desec::DeSec, digitalocean::DigitalOcean, dnsimple::Dnsimple,
+        dnsmadeeasy::DnsMadeEasy, gandi::Gandi, porkbun::Porkbun, AsyncDnsProvider,
+    },
+    Provider, RecordType,
+};
 
-pub fn get_dns_provider(config: &Config) -> Box<dyn AsyncDnsProvider> {
+use crate::{config::Config, metrics};
+
+/// The concrete async provider implementations.
+///
+/// The boxed `AsyncDnsProvider` returned by `Provider::async_impl()` only
+/// exposes the A-record helpers; the generic record methods (needed for AAAA)
+/// require a sized type, so we keep the concrete client around instead.
+enum Client {
+    Cloudflare(Cloudflare),
+    DeSec(DeSec),
+    DigitalOcean(DigitalOcean),
+    Gandi(Gandi),
+    Dnsimple(Dnsimple),
+    DnsMadeEasy(DnsMadeEasy),
+    PorkBun(Porkbun),
+}
+
+macro_rules! dispatch {
+    ($client:expr, $c:ident => $e:expr) => {
+        match $client {
+            Client::Cloudflare($c) => $e,
+            Client::DeSec($c) => $e,
+            Client::DigitalOcean($c) => $e,
+            Client::Gandi($c) => $e,
+            Client::Dnsimple($c) => $e,
+            Client::DnsMadeEasy($c) => $e,
+            Client::PorkBun($c) => $e,
+        }
+    };
+}
+
+/// The name of the record type used for addresses of `family`.
+pub fn rtype(family: AddressFamily) -> &'static str {
+    match family {
+        AddressFamily::Inet6 => "AAAA",
+        _ => "A",
+    }
+}
+
+/// A DNS provider client that can manage both A and AAAA records.
+pub struct DnsClient {
+    client: Client,
+}
+
+fn record_type(family: AddressFamily) -> Result<RecordType> {
+    match family {
+        AddressFamily::Inet => Ok(RecordType::A),
+        AddressFamily::Inet6 => Ok(RecordType::AAAA),
+        _ => bail!("Unsupported address family {family:?}"),
+    }
+}
+
+/// Reports the latency of a provider call when dropped, so calls that fail
+/// are measured too.
+struct CallTimer {
+    operation: &'static str,
+    start: Instant,
+}
+
+impl CallTimer {
+    fn new(operation: &'static str) -> Self {
+        CallTimer { operation, start: Instant::now() }
+    }
+}
+
+impl Drop for CallTimer {
+    fn drop(&mut self) {
+        metrics::provider_call(self.operation, self.start.elapsed());
+    }
+}
+
+impl DnsClient {
+    /// Fetch the published address record for `host` (A for `Inet`, AAAA for `Inet6`).
+    pub async fn get_addr_record(&self, host: &String, family: AddressFamily) -> Result<Option<IpAddr>> {
+        let rtype = record_type(family)?;
+        let _timer = CallTimer::new("get");
+        let addr = match family {
+            AddressFamily::Inet => {
+                dispatch!(&self.client, c => c.get_record::<Ipv4Addr>(rtype, host).await?)
+                    .map(IpAddr::V4)
+            }
+            _ => {
+                dispatch!(&self.client, c => c.get_record::<Ipv6Addr>(rtype, host).await?)
+                    .map(IpAddr::V6)
+            }
+        };
+        Ok(addr)
+    }
+
+    /// Create an A or AAAA record for `host`, depending on the address type.
+    pub async fn create_addr_record(&self, host: &String, addr: &IpAddr) -> Result<()> {
+        let _timer = CallTimer::new("create");
+        match addr {
+            IpAddr::V4(ip) => dispatch!(&self.client, c => c.create_record(RecordType::A, host, ip).await?),
+            IpAddr::V6(ip) => dispatch!(&self.client, c => c.create_record(RecordType::AAAA, host, ip).await?),
+        }
+        Ok(())
+    }
+
+    /// Update the A or AAAA record for `host`, depending on the address type.
+    pub async fn update_addr_record(&self, host: &String, addr: &IpAddr) -> Result<()> {
+        let _timer = CallTimer::new("update");
+        match addr {
+            IpAddr::V4(ip) => dispatch!(&self.client, c => c.update_record(RecordType::A, host, ip).await?),
+            IpAddr::V6(ip) => dispatch!(&self.client, c => c.update_record(RecordType::AAAA, host, ip).await?),
+        }
+        Ok(())
+    }
+
+    /// Delete the A or AAAA record for `host`.
+    pub async fn delete_addr_record(&self, host: &String, family: AddressFamily) -> Result<()> {
+        let rtype = record_type(family)?;
+        let _timer = CallTimer::new("delete");
+        dispatch!(&self.client, c => c.delete_record(rtype, host).await?);
+        Ok(())
+    }
+}
+
+/// The config name of a provider; e.g. "porkbun".
+pub fn provider_name(provider: &Provider) -> &'static str {
+    match provider {
+        Provider::Cloudflare(_) => "cloudflare",
+        Provider::DeSec(_) => "desec",
+        Provider::DigitalOcean(_) => "digitalocean",
+        Provider::Gandi(_) => "gandi",
+        Provider::Dnsimple(_) => "dnsimple",
+        Provider::DnsMadeEasy(_) => "dnsmadeeasy",
+        Provider::PorkBun(_) => "porkbun",
+        _ => "unknown",
+    }
+}
+
+pub fn get_dns_provider(config: &Config, domain: &str) -> Result<DnsClient> {
 
     let dns_conf = zone_update::Config {
-        domain: config.ddns.domain.clone(),
+        domain: domain.to_owned(),
         dry_run: config.dry_run,
     };
 
-    config.ddns.provider.async_impl(dns_conf)
+    let client = match &*config.ddns.provider {
+        Provider::Cloudflare(auth) => Client::Cloudflare(Cloudflare::new(dns_conf, auth.clone())),
+        Provider::DeSec(auth) => Client::DeSec(DeSec::new(dns_conf, auth.clone())),
+        Provider::DigitalOcean(auth) => Client::DigitalOcean(DigitalOcean::new(dns_conf, auth.clone())),
+        Provider::Gandi(auth) => Client::Gandi(Gandi::new(dns_conf, auth.clone())),
+        Provider::Dnsimple(auth) => Client::Dnsimple(Dnsimple::new(dns_conf, auth.clone(), None)),
+        Provider::DnsMadeEasy(auth) => Client::DnsMadeEasy(DnsMadeEasy::new(dns_conf, auth.clone())),
+        Provider::PorkBun(auth) => Client::PorkBun(Porkbun::new(dns_conf, auth.clone())),
+        _ => bail!("Unsupported DNS provider"),
+    };
+
+    Ok(DnsClient { client })
 }
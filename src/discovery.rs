@@ -0,0 +1,315 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use rtnetlink::packet_route::AddressFamily;
+use tracing::debug;
+use ureq::{config::IpFamily, Agent};
+
+use crate::config::AddrSource;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_RESPONSE: u16 = 0x0101;
+const STUN_MAGIC_COOKIE: u32 = 0x2112_A442;
+const STUN_ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const STUN_ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const STUN_HEADER_LEN: usize = 20;
+
+/// Look up our public address of the given family from `source`.
+///
+/// Returns an error for `AddrSource::Netlink`, which has no external lookup.
+pub async fn discover(source: &AddrSource, family: AddressFamily) -> Result<IpAddr> {
+    let source = source.clone();
+    let ip = compio::runtime::spawn_blocking(move || match source {
+        AddrSource::Http(url) => http_lookup(&url, family),
+        AddrSource::Stun(server) => stun_lookup(&server, family),
+        AddrSource::Netlink => bail!("Netlink is not a discovery source"),
+    })
+    .await
+    .map_err(|_| anyhow!("Address discovery task panicked"))??;
+
+    debug!("Discovered public address {ip}");
+    Ok(ip)
+}
+
+fn check_family(ip: IpAddr, family: AddressFamily) -> Result<IpAddr> {
+    match (ip, family) {
+        (IpAddr::V4(_), AddressFamily::Inet) | (IpAddr::V6(_), AddressFamily::Inet6) => Ok(ip),
+        _ => bail!("Discovered address {ip} is not of family {family:?}"),
+    }
+}
+
+fn http_lookup(url: &str, family: AddressFamily) -> Result<IpAddr> {
+    let ip_family = match family {
+        AddressFamily::Inet6 => IpFamily::Ipv6Only,
+        _ => IpFamily::Ipv4Only,
+    };
+    let agent: Agent = Agent::config_builder()
+        .ip_family(ip_family)
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    let body = agent.get(url)
+        .call()
+        .with_context(|| format!("Failed to query {url}"))?
+        .body_mut()
+        .read_to_string()?;
+    let ip = body.trim().parse::<IpAddr>()
+        .with_context(|| format!("Invalid address returned by {url}: {body:?}"))?;
+
+    check_family(ip, family)
+}
+
+fn stun_lookup(server: &str, family: AddressFamily) -> Result<IpAddr> {
+    let server_addr = server.to_socket_addrs()
+        .with_context(|| format!("Failed to resolve STUN server {server}"))?
+        .find(|a| (family == AddressFamily::Inet6) == a.is_ipv6())
+        .with_context(|| format!("No {family:?} address found for STUN server {server}"))?;
+
+    let bind = match server_addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server_addr)?;
+
+    let txid: [u8; 12] = std::array::from_fn(|_| fastrand::u8(..));
+    socket.send(&stun_request(&txid))?;
+
+    let mut buf = [0u8; 512];
+    let len = socket.recv(&mut buf)
+        .with_context(|| format!("No response from STUN server {server}"))?;
+    let ip = parse_stun_response(&buf[..len], &txid)?;
+
+    check_family(ip, family)
+}
+
+fn stun_request(txid: &[u8; 12]) -> Vec<u8> {
+    let mut req = Vec::with_capacity(STUN_HEADER_LEN);
+    req.extend_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+    req.extend_from_slice(&0u16.to_be_bytes());
+    req.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    req.extend_from_slice(txid);
+    req
+}
+
+fn parse_stun_addr(value: &[u8], xor: Option<&[u8; 16]>) -> Result<IpAddr> {
+    let (family, addr) = match value {
+        [_, family, _, _, addr @ ..] => (*family, addr),
+        _ => bail!("Truncated STUN address attribute"),
+    };
+    let unmask = |bytes: &[u8]| -> Vec<u8> {
+        match xor {
+            Some(key) => bytes.iter().zip(key.iter()).map(|(b, k)| b ^ k).collect(),
+            None => bytes.to_vec(),
+        }
+    };
+
+    match (family, addr.len()) {
+        (0x01, 4) => {
+            let octets: [u8; 4] = unmask(addr).try_into().unwrap();
+            Ok(IpAddr::from(octets))
+        }
+        (0x02, 16) => {
+            let octets: [u8; 16] = unmask(addr).try_into().unwrap();
+            Ok(IpAddr::from(octets))
+        }
+        _ => bail!("Invalid STUN address attribute"),
+    }
+}
+
+fn parse_stun_response(msg: &[u8], txid: &[u8; 12]) -> Result<IpAddr> {
+    if msg.len() < STUN_HEADER_LEN {
+        bail!("Truncated STUN response");
+    }
+    let mtype = u16::from_be_bytes([msg[0], msg[1]]);
+    let mlen = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    if mtype != STUN_BINDING_RESPONSE {
+        bail!("Unexpected STUN message type {mtype:#06x}");
+    }
+    if msg[4..8] != STUN_MAGIC_COOKIE.to_be_bytes() || msg[8..20] != txid[..] {
+        bail!("STUN response doesn't match request");
+    }
+
+    // XOR-MAPPED-ADDRESS is masked with the cookie followed by the transaction ID.
+    let mut key = [0u8; 16];
+    key[..4].copy_from_slice(&msg[4..8]);
+    key[4..].copy_from_slice(txid);
+
+    let attrs = msg.get(STUN_HEADER_LEN..STUN_HEADER_LEN + mlen)
+        .context("Truncated STUN response")?;
+    let mut mapped = None;
+    let mut pos = 0;
+    while pos + 4 <= attrs.len() {
+        let atype = u16::from_be_bytes([attrs[pos], attrs[pos + 1]]);
+        let alen = u16::from_be_bytes([attrs[pos + 2], attrs[pos + 3]]) as usize;
+        let value = attrs.get(pos + 4..pos + 4 + alen)
+            .context("Truncated STUN attribute")?;
+        match atype {
+            STUN_ATTR_XOR_MAPPED_ADDRESS => return parse_stun_addr(value, Some(&key)),
+            STUN_ATTR_MAPPED_ADDRESS => mapped = Some(parse_stun_addr(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to 4 bytes
+        pos += 4 + alen.div_ceil(4) * 4;
+    }
+
+    mapped.context("No mapped address in STUN response")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    const TXID: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+    fn stun_response(txid: &[u8; 12], atype: u16, value: &[u8]) -> Vec<u8> {
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&STUN_BINDING_RESPONSE.to_be_bytes());
+        msg.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+        msg.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(txid);
+        msg.extend_from_slice(&atype.to_be_bytes());
+        msg.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        msg.extend_from_slice(value);
+        msg
+    }
+
+    fn xor_v4(ip: Ipv4Addr) -> Vec<u8> {
+        let cookie = STUN_MAGIC_COOKIE.to_be_bytes();
+        let mut value = vec![0, 0x01, 0x12, 0x34];
+        value.extend(ip.octets().iter().zip(cookie.iter()).map(|(b, k)| b ^ k));
+        value
+    }
+
+    /// Serve a single HTTP request with `body`, returning the URL to fetch.
+    fn http_stand_in(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                               body.len());
+            stream.write_all(resp.as_bytes()).unwrap();
+        });
+        format!("http://{addr}/")
+    }
+
+    #[test]
+    fn test_parse_xor_mapped_v4() -> Result<()> {
+        let ip = Ipv4Addr::new(203, 0, 113, 5);
+        let msg = stun_response(&TXID, STUN_ATTR_XOR_MAPPED_ADDRESS, &xor_v4(ip));
+        assert_eq!(parse_stun_response(&msg, &TXID)?, IpAddr::V4(ip));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_xor_mapped_v6() -> Result<()> {
+        let ip: Ipv6Addr = "2001:db8::5".parse()?;
+        let mut key = STUN_MAGIC_COOKIE.to_be_bytes().to_vec();
+        key.extend_from_slice(&TXID);
+        let mut value = vec![0, 0x02, 0x12, 0x34];
+        value.extend(ip.octets().iter().zip(key.iter()).map(|(b, k)| b ^ k));
+
+        let msg = stun_response(&TXID, STUN_ATTR_XOR_MAPPED_ADDRESS, &value);
+        assert_eq!(parse_stun_response(&msg, &TXID)?, IpAddr::V6(ip));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_mapped_v4() -> Result<()> {
+        let msg = stun_response(&TXID, STUN_ATTR_MAPPED_ADDRESS, &[0, 0x01, 0x12, 0x34, 198, 51, 100, 7]);
+        assert_eq!(parse_stun_response(&msg, &TXID)?, IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_wrong_txid() {
+        let msg = stun_response(&TXID, STUN_ATTR_XOR_MAPPED_ADDRESS, &xor_v4(Ipv4Addr::LOCALHOST));
+        assert!(parse_stun_response(&msg, &[0; 12]).is_err());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let msg = stun_response(&TXID, STUN_ATTR_XOR_MAPPED_ADDRESS, &xor_v4(Ipv4Addr::LOCALHOST));
+        assert!(parse_stun_response(&msg[..msg.len() - 2], &TXID).is_err());
+        assert!(parse_stun_response(&msg[..10], &TXID).is_err());
+    }
+
+    #[test]
+    fn test_stun_lookup() -> Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0")?;
+        let addr = server.local_addr()?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(len, STUN_HEADER_LEN);
+            let txid: [u8; 12] = buf[8..20].try_into().unwrap();
+            let resp = stun_response(&txid, STUN_ATTR_XOR_MAPPED_ADDRESS,
+                                     &xor_v4(Ipv4Addr::new(203, 0, 113, 9)));
+            server.send_to(&resp, peer).unwrap();
+        });
+
+        let ip = stun_lookup(&addr.to_string(), AddressFamily::Inet)?;
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(203, 0, 113, 9)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_lookup() -> Result<()> {
+        let url = http_stand_in("203.0.113.42\n");
+        let ip = http_lookup(&url, AddressFamily::Inet)?;
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(203, 0, 113, 42)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_lookup_garbage() {
+        let url = http_stand_in("<html>not an address</html>");
+        assert!(http_lookup(&url, AddressFamily::Inet).is_err());
+    }
+
+    #[test]
+    fn test_http_lookup_wrong_family() {
+        let url = http_stand_in("2001:db8::1");
+        assert!(http_lookup(&url, AddressFamily::Inet).is_err());
+    }
+
+    #[compio::test]
+    async fn test_discover_http() -> Result<()> {
+        let url = http_stand_in("198.51.100.1");
+        let ip = discover(&AddrSource::Http(url), AddressFamily::Inet).await?;
+        assert_eq!(ip, IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1)));
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    fmt::{self, Display, Formatter},
+    net::IpAddr,
+};
+
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+
+/// The broad category of an address, as far as publishing it is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddrClass {
+    /// Publicly routable
+    Global,
+    /// RFC1918 private (IPv4) or unique local (IPv6)
+    Private,
+    /// RFC6598 shared address space used for carrier-grade NAT
+    SharedCgnat,
+    /// Link-local (169.254/16, fe80::/10)
+    LinkLocal,
+    /// Loopback
+    Loopback,
+    /// Documentation, benchmarking and other reserved or non-unicast ranges
+    Reserved,
+}
+
+impl Display for AddrClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AddrClass::Global => "global",
+            AddrClass::Private => "private",
+            AddrClass::SharedCgnat => "CGNAT",
+            AddrClass::LinkLocal => "link-local",
+            AddrClass::Loopback => "loopback",
+            AddrClass::Reserved => "reserved",
+        };
+        write!(f, "{s}")
+    }
+}
+
+fn nets(cidrs: &[&str]) -> Vec<IpNet> {
+    cidrs.iter()
+        .map(|c| c.parse().expect("Invalid built-in network"))
+        .collect()
+}
+
+static PRIVATE: Lazy<Vec<IpNet>> = Lazy::new(|| nets(&[
+    "10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fc00::/7",
+]));
+static SHARED: Lazy<Vec<IpNet>> = Lazy::new(|| nets(&[
+    "100.64.0.0/10",
+]));
+static LINK_LOCAL: Lazy<Vec<IpNet>> = Lazy::new(|| nets(&[
+    "169.254.0.0/16", "fe80::/10",
+]));
+static LOOPBACK: Lazy<Vec<IpNet>> = Lazy::new(|| nets(&[
+    "127.0.0.0/8", "::1/128",
+]));
+static RESERVED: Lazy<Vec<IpNet>> = Lazy::new(|| nets(&[
+    // IPv4: "this network", IETF protocol assignments, documentation,
+    // benchmarking, multicast and future use (including broadcast).
+    "0.0.0.0/8", "192.0.0.0/24", "192.0.2.0/24", "198.18.0.0/15",
+    "198.51.100.0/24", "203.0.113.0/24", "224.0.0.0/4", "240.0.0.0/4",
+    // IPv6: unspecified, IPv4-mapped, discard, documentation and multicast.
+    "::/128", "::ffff:0:0/96", "100::/64", "2001:db8::/32", "ff00::/8",
+]));
+
+/// Classify an address by the special-purpose range it falls in, if any.
+pub fn classify(ip: &IpAddr) -> AddrClass {
+    let within = |nets: &[IpNet]| nets.iter().any(|n| n.contains(ip));
+
+    if within(&LOOPBACK) {
+        AddrClass::Loopback
+    } else if within(&LINK_LOCAL) {
+        AddrClass::LinkLocal
+    } else if within(&PRIVATE) {
+        AddrClass::Private
+    } else if within(&SHARED) {
+        AddrClass::SharedCgnat
+    } else if within(&RESERVED) {
+        AddrClass::Reserved
+    } else {
+        AddrClass::Global
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(ip: &str) -> AddrClass {
+        classify(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_classify_v4() {
+        assert_eq!(class("8.8.8.8"), AddrClass::Global);
+        assert_eq!(class("100.63.255.255"), AddrClass::Global);
+        assert_eq!(class("10.1.2.3"), AddrClass::Private);
+        assert_eq!(class("172.31.0.1"), AddrClass::Private);
+        assert_eq!(class("192.168.1.1"), AddrClass::Private);
+        assert_eq!(class("100.64.0.1"), AddrClass::SharedCgnat);
+        assert_eq!(class("100.127.255.254"), AddrClass::SharedCgnat);
+        assert_eq!(class("169.254.10.1"), AddrClass::LinkLocal);
+        assert_eq!(class("127.0.0.1"), AddrClass::Loopback);
+        assert_eq!(class("203.0.113.5"), AddrClass::Reserved);
+        assert_eq!(class("255.255.255.255"), AddrClass::Reserved);
+    }
+
+    #[test]
+    fn test_classify_v6() {
+        assert_eq!(class("2606:4700::1111"), AddrClass::Global);
+        assert_eq!(class("fd12:3456::1"), AddrClass::Private);
+        assert_eq!(class("fe80::1"), AddrClass::LinkLocal);
+        assert_eq!(class("::1"), AddrClass::Loopback);
+        assert_eq!(class("2001:db8::1"), AddrClass::Reserved);
+        assert_eq!(class("::ffff:10.0.0.1"), AddrClass::Reserved);
+    }
+}
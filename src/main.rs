@@ -14,18 +14,59 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+mod commands;
 mod config;
 mod ddns;
+mod discovery;
+mod filter;
+mod metrics;
 mod netlink;
+mod notify;
+mod pattern;
+mod secrets;
+mod signals;
+mod state;
+mod updater;
 
-use std::{str::FromStr, time::Duration};
+use std::{
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    str::FromStr,
+    task::Poll,
+    time::Duration,
+};
 
-use anyhow::Result;
-use futures::stream::StreamExt;
-use tracing::{info, warn};
+use anyhow::{bail, Result};
+use compio::runtime::Runtime;
+use futures::{
+    future::{pending, select, Either},
+    FutureExt, StreamExt,
+};
+use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, filter::LevelFilter};
 
-use crate::{config::CliOptions, ddns::get_dns_provider, netlink::ChangeType};
+use crate::{
+    config::{CliOptions, Command, Config},
+    netlink::{ChangeType, IfEvent},
+    signals::Signal,
+    updater::{Status, Updater},
+};
+
+/// How long to wait for in-flight updates on shutdown; this should be less
+/// than `TimeoutStopSec` in the systemd unit.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Things that wake up the main loop.
+enum Wake {
+    Message(Option<IfEvent>),
+    Signal(Signal),
+    Watchdog,
+}
+
+fn timer(delay: Duration) -> Pin<Box<dyn Future<Output = ()>>> {
+    Box::pin(compio::runtime::time::sleep(delay))
+}
 
 fn init_logging(level: &Option<String>) -> Result<()> {
     let lf = level.clone()
@@ -45,65 +86,223 @@ fn init_logging(level: &Option<String>) -> Result<()> {
     Ok(())
 }
 
+/// Apply an interface event to the updater.
+async fn handle_event(updater: &Updater, event: IfEvent) {
+    let message = match event {
+        IfEvent::Addr(message) => message,
+        IfEvent::Link(change) => {
+            updater.link_changed(&change).await;
+            return;
+        }
+    };
+
+    metrics::netlink_event(&message.ctype);
+
+    match message.ctype {
+        ChangeType::Add => {
+            let ip = message.addr.ip;
+            match message.addr.peer {
+                Some(peer) => info!("Received new address on {}: {ip} (peer {peer})", message.iface),
+                None => info!("Received new address on {}: {ip}", message.iface),
+            }
+            updater.publish(&message.iface, &[message.addr]);
+        }
+        ChangeType::Del => {
+            let ip = message.addr.ip;
+            info!("IP {ip} was deleted from iface {}", message.iface);
+            updater.remove(&message.iface, &message.addr);
+        }
+    }
+}
+
+/// Re-read the config file and apply any changes to the updater; the
+/// running config is kept if the new one fails to load or apply.
+fn reload(cli: &CliOptions, config: &mut Config, updater: &mut Updater) -> Result<()> {
+    let new = config::get_config(&cli.config, cli.format)?;
+    if new.log_level != config.log_level {
+        warn!("Changes to `log_level` require a restart");
+    }
+    if new.metrics != config.metrics {
+        warn!("Changes to `metrics` require a restart");
+    }
+    updater.reload(&new)?;
+    *config = new;
+    Ok(())
+}
+
+/// Stop the updater, letting in-flight updates finish.
+async fn shutdown(updater: &Updater) {
+    info!("Shutting down");
+    notify::stopping();
+    updater.shutdown(SHUTDOWN_TIMEOUT).await;
+}
+
 fn main() -> Result<()> {
     let cli = CliOptions::from_args()?;
-    let config = config::get_config(&cli.config)?;
+    match cli.command {
+        Command::Run => run(&cli),
+        Command::Check { online } => {
+            init_logging(&Some("warn".to_string()))?;
+            Runtime::new()?.block_on(commands::check(&cli, online))
+        }
+        Command::SyncOnce => {
+            let config = load_config(&cli)?;
+            let status = Runtime::new()?.block_on(commands::sync_once(&config))?;
+            std::process::exit(status);
+        }
+        Command::Get { ref host } => {
+            let config = load_config(&cli)?;
+            Runtime::new()?.block_on(commands::get(&config, host))
+        }
+        Command::Set { ref host, ip } => {
+            let config = load_config(&cli)?;
+            Runtime::new()?.block_on(commands::set(&config, host, ip))
+        }
+        Command::Delete { ref host } => {
+            let config = load_config(&cli)?;
+            Runtime::new()?.block_on(commands::delete(&config, host))
+        }
+        Command::PrintConfig => {
+            let config = config::get_config(&cli.config, cli.format)?;
+            commands::print_config(&config)
+        }
+    }
+}
+
+/// Load the config for a one-off command and start logging.
+fn load_config(cli: &CliOptions) -> Result<Config> {
+    let config = config::get_config(&cli.config, cli.format)?;
     init_logging(&config.log_level)?;
-    info!("Starting...");
+    Ok(config)
+}
 
-    let ddns = get_dns_provider(&config);
+/// Run the daemon.
+fn run(cli: &CliOptions) -> Result<()> {
+    let mut config = config::get_config(&cli.config, cli.format)?;
+    init_logging(&config.log_level)?;
+    info!("Starting...");
 
-    compio::runtime::Runtime::new()?.block_on(async {
-        info!("Waiting for {} to come up...", config.iface);
+    let ifaces = config.interfaces();
+    let mut signals = signals::signal_stream()?;
 
-        let local = loop {
-            let attempt = netlink::get_if_addr(&config.iface).await;
-            if let Ok(Some(ip)) = attempt {
-                info!("IP Addr valid on {}", config.iface);
-                break ip;
-            }
-            warn!("Error getting IP: {attempt:?}; sleeping");
-            compio::runtime::time::sleep(Duration::from_secs(10)).await;
-        };
+    Runtime::new()?.block_on(async {
+        if let Some(m) = &config.metrics {
+            metrics::start(m.listen).await?;
+        }
+        let mut updater = Updater::new(&config)?;
 
-        info!("Fetching published DNS record");
-        let mut upstream = ddns.get_a_record(&config.ddns.host).await?;
+        // Signals other than shutdown are handled once startup completes.
+        let mut deferred = VecDeque::new();
+        let startup = async {
+            info!("Starting monitoring stream");
+            let msgs = netlink::iface_stream(ifaces.clone(), &config.all_families()).await?;
 
-        if upstream.is_none()  {
-            info!("No existing DNS record; creating");
-            ddns.create_a_record(&config.ddns.host, &local).await?;
+            // Publish whatever is available now; interfaces without an
+            // address are picked up from the monitoring stream, so a slow or
+            // missing interface doesn't hold up startup.
+            let waiting = updater.publish_current().await;
 
-        } else if Some(local) != upstream {
-            info!("DNS record out of date; updating");
-            ddns.update_a_record(&config.ddns.host, &local).await?;
+            let synced = updater.settle().await;
+            let failed = synced.iter()
+                .filter(|(_, _, status)| *status == Status::Failed)
+                .count();
+            let mut status = if failed == 0 {
+                format!("synced {} records", synced.len())
+            } else {
+                format!("{failed} of {} records failed to sync; retrying", synced.len())
+            };
+            if !waiting.is_empty() {
+                let mut ifaces = waiting.iter()
+                    .map(|(iface, _)| iface.as_str())
+                    .collect::<Vec<_>>();
+                ifaces.dedup();
+                info!("Waiting for {} to come up...", ifaces.join(", "));
+                status = format!("{status}; waiting for {}", ifaces.join(", "));
+            }
+            notify::ready(&status);
+            Ok::<_, anyhow::Error>(msgs)
+        };
+        let interrupted = async {
+            while let Some(signal) = signals.next().await {
+                match signal {
+                    Signal::Shutdown => return,
+                    signal => {
+                        info!("Deferring {signal:?} request until startup completes");
+                        deferred.push_back(signal);
+                    }
+                }
+            }
+            pending::<()>().await
+        };
+        let mut msgs = match select(pin!(startup), pin!(interrupted)).await {
+            Either::Left((msgs, _)) => msgs?,
+            Either::Right(_) => {
+                shutdown(&updater).await;
+                return Ok(());
+            }
+        };
 
-        } else {
-            info!("DNS record is up-to-date: {local}");
-        }
+        // The watchdog is only pinged while the monitoring stream is alive; if
+        // the stream ends, exit with an error so the service is restarted.
+        let watchdog = notify::watchdog_interval();
+        let mut tick = watchdog.map(timer);
+        loop {
+            let wake = poll_fn(|cx| {
+                if let Some(t) = tick.as_mut() && t.poll_unpin(cx).is_ready() {
+                    return Poll::Ready(Wake::Watchdog);
+                }
+                if let Some(signal) = deferred.pop_front() {
+                    return Poll::Ready(Wake::Signal(signal));
+                }
+                if let Poll::Ready(Some(signal)) = signals.poll_next_unpin(cx) {
+                    return Poll::Ready(Wake::Signal(signal));
+                }
+                msgs.poll_next_unpin(cx).map(Wake::Message)
+            }).await;
 
-        info!("Starting monitoring stream");
-        let mut msgs = netlink::ipv4_addr_stream(&config.iface).await?;
-        while let Some(message) = msgs.next().await {
-            match message.ctype {
-                ChangeType::Add => {
-                    let ip = message.addr;
-                    info!("Received new address: {ip}");
-                    if upstream.is_some_and(|uip| uip == ip)
-                    {
-                        info!("IP {ip} matches upstream, skipping");
+            let event = match wake {
+                Wake::Message(Some(event)) => event,
+                Wake::Message(None) => {
+                    shutdown(&updater).await;
+                    bail!("Interface monitoring stream ended unexpectedly");
+                }
+                Wake::Watchdog => {
+                    notify::watchdog();
+                    tick = watchdog.map(timer);
+                    continue;
+                }
+                Wake::Signal(Signal::Resync) => {
+                    info!("Forcing a re-sync of all records");
+                    updater.resync().await;
+                    continue;
+                }
+                Wake::Signal(Signal::Reload) => {
+                    info!("Reloading configuration");
+                    let (ifaces, families) = (config.interfaces(), config.all_families());
+                    if let Err(err) = reload(cli, &mut config, &mut updater) {
+                        error!("Failed to reload configuration; keeping the running config: {err:#}");
                         continue;
                     }
-
-                    info!("Setting DNS record");
-                    ddns.update_a_record(&config.ddns.host, &ip).await?;
-                    info!("DNS Set");
-                    upstream = Some(ip);
+                    if config.interfaces() != ifaces || config.all_families() != families {
+                        info!("Re-subscribing to interface changes");
+                        match netlink::iface_stream(config.interfaces(), &config.all_families()).await {
+                            Ok(stream) => msgs = stream,
+                            Err(err) => error!("Failed to re-subscribe to interface changes: {err:#}"),
+                        }
+                    }
+                    updater.publish_current().await;
+                    continue;
                 }
-                ChangeType::Del => {
-                    let ip = message.addr;
-                    info!("IP {ip} was deleted from iface {}", config.iface);
+                Wake::Signal(Signal::Shutdown) => {
+                    // Dropping the stream stops the netlink subscription at
+                    // the next event.
+                    drop(msgs);
+                    shutdown(&updater).await;
+                    break;
                 }
-            }
+            };
+
+            handle_event(&updater, event).await;
         }
 
         Ok(())
@@ -0,0 +1,357 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use compio::{
+    io::{AsyncRead, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    BufResult,
+};
+use once_cell::sync::Lazy;
+use rtnetlink::packet_route::AddressFamily;
+use tracing::{debug, info, warn};
+
+use crate::{config::HostConfig, ddns::rtype, netlink::ChangeType};
+
+/// Upper bounds of the provider latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Requests larger than this are rejected.
+const MAX_REQUEST: usize = 8192;
+
+static METRICS: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+/// Identifies a published record; the FQDN and record type.
+type RecordLabels = (String, &'static str);
+
+fn record_labels(host: &HostConfig, family: AddressFamily) -> RecordLabels {
+    (host.fqdn(), rtype(family))
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// The collected metrics.
+#[derive(Debug, Default)]
+struct Registry {
+    netlink_events: BTreeMap<&'static str, u64>,
+    attempts: BTreeMap<RecordLabels, u64>,
+    successes: BTreeMap<RecordLabels, u64>,
+    failures: BTreeMap<RecordLabels, u64>,
+    drift: BTreeMap<RecordLabels, u64>,
+    last_success: BTreeMap<RecordLabels, u64>,
+    published: BTreeMap<RecordLabels, IpAddr>,
+    latency: BTreeMap<&'static str, Histogram>,
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_records(out: &mut String, name: &str, kind: &str, help: &str,
+                 values: &BTreeMap<RecordLabels, u64>)
+{
+    write_header(out, name, kind, help);
+    for ((host, rtype), value) in values {
+        let _ = writeln!(out, "{name}{{host=\"{}\",type=\"{rtype}\"}} {value}", escape(host));
+    }
+}
+
+impl Registry {
+    /// Render the metrics in the Prometheus text exposition format.
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let name = "netlink_ddns_netlink_events_total";
+        write_header(&mut out, name, "counter", "Netlink address events received.");
+        for (ctype, value) in &self.netlink_events {
+            let _ = writeln!(out, "{name}{{change=\"{ctype}\"}} {value}");
+        }
+
+        write_records(&mut out, "netlink_ddns_update_attempts_total", "counter",
+                      "Record updates attempted.", &self.attempts);
+        write_records(&mut out, "netlink_ddns_update_successes_total", "counter",
+                      "Record updates that succeeded.", &self.successes);
+        write_records(&mut out, "netlink_ddns_update_failures_total", "counter",
+                      "Record updates that failed.", &self.failures);
+        write_records(&mut out, "netlink_ddns_drift_total", "counter",
+                      "Upstream records found changed outside the daemon.", &self.drift);
+        write_records(&mut out, "netlink_ddns_last_success_timestamp_seconds", "gauge",
+                      "When the record was last successfully updated.", &self.last_success);
+
+        let name = "netlink_ddns_published_address";
+        write_header(&mut out, name, "gauge", "The currently published address of a record.");
+        for ((host, rtype), ip) in &self.published {
+            let _ = writeln!(out, "{name}{{host=\"{}\",type=\"{rtype}\",address=\"{ip}\"}} 1",
+                             escape(host));
+        }
+
+        let name = "netlink_ddns_provider_request_duration_seconds";
+        write_header(&mut out, name, "histogram", "Latency of DNS provider API calls.");
+        for (op, hist) in &self.latency {
+            for (le, count) in LATENCY_BUCKETS.iter().zip(hist.buckets) {
+                let _ = writeln!(out, "{name}_bucket{{operation=\"{op}\",le=\"{le}\"}} {count}");
+            }
+            let _ = writeln!(out, "{name}_bucket{{operation=\"{op}\",le=\"+Inf\"}} {}", hist.count);
+            let _ = writeln!(out, "{name}_sum{{operation=\"{op}\"}} {}", hist.sum);
+            let _ = writeln!(out, "{name}_count{{operation=\"{op}\"}} {}", hist.count);
+        }
+
+        out
+    }
+}
+
+fn with_registry(f: impl FnOnce(&mut Registry)) {
+    // The metrics are still usable if a panic poisoned the lock.
+    let mut registry = METRICS.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut registry)
+}
+
+pub fn netlink_event(ctype: &ChangeType) {
+    let label = match ctype {
+        ChangeType::Add => "add",
+        ChangeType::Del => "del",
+    };
+    with_registry(|r| *r.netlink_events.entry(label).or_default() += 1);
+}
+
+pub fn update_attempted(host: &HostConfig, family: AddressFamily) {
+    with_registry(|r| *r.attempts.entry(record_labels(host, family)).or_default() += 1);
+}
+
+pub fn update_failed(host: &HostConfig, family: AddressFamily) {
+    with_registry(|r| *r.failures.entry(record_labels(host, family)).or_default() += 1);
+}
+
+/// Record a successful update, along with the address now published.
+pub fn update_succeeded(host: &HostConfig, family: AddressFamily, published: Option<IpAddr>) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let labels = record_labels(host, family);
+    with_registry(|r| {
+        *r.successes.entry(labels.clone()).or_default() += 1;
+        r.last_success.insert(labels.clone(), now);
+        match published {
+            Some(ip) => r.published.insert(labels, ip),
+            None => r.published.remove(&labels),
+        };
+    });
+}
+
+pub fn drift_detected(host: &HostConfig, family: AddressFamily) {
+    with_registry(|r| *r.drift.entry(record_labels(host, family)).or_default() += 1);
+}
+
+/// Record the latency of a provider API call.
+pub fn provider_call(operation: &'static str, elapsed: Duration) {
+    with_registry(|r| r.latency.entry(operation).or_default().observe(elapsed.as_secs_f64()));
+}
+
+fn response(status: &str, content_type: &str, body: String) -> Vec<u8> {
+    format!("HTTP/1.1 {status}\r\n\
+             Content-Type: {content_type}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {body}", body.len())
+        .into_bytes()
+}
+
+async fn handle(mut stream: TcpStream) -> Result<()> {
+    let mut request = Vec::new();
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST {
+            stream.write_all(response("413 Content Too Large", "text/plain", String::new())).await.0?;
+            return Ok(());
+        }
+        let BufResult(read, buf) = stream.read(Vec::with_capacity(1024)).await;
+        if read? == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf);
+    }
+
+    let line = String::from_utf8_lossy(&request);
+    let mut parts = line.split_whitespace();
+    let reply = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = METRICS.lock().unwrap_or_else(|e| e.into_inner()).render();
+            response("200 OK", "text/plain; version=0.0.4", body)
+        }
+        (Some("GET"), _) => response("404 Not Found", "text/plain", "Not found\n".to_string()),
+        _ => response("405 Method Not Allowed", "text/plain", String::new()),
+    };
+    stream.write_all(reply).await.0?;
+    Ok(())
+}
+
+async fn serve(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                compio::runtime::spawn(async move {
+                    if let Err(err) = handle(stream).await {
+                        debug!("Metrics request from {peer} failed: {err:#}");
+                    }
+                }).detach();
+            }
+            Err(err) => warn!("Failed to accept metrics connection: {err}"),
+        }
+    }
+}
+
+/// Start serving the metrics over HTTP on `addr`; must be called from
+/// within the runtime.
+pub async fn start(addr: SocketAddr) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr).await
+        .with_context(|| format!("Failed to bind metrics listener to {addr}"))?;
+    let local = listener.local_addr()?;
+    info!("Serving metrics on http://{local}/metrics");
+    compio::runtime::spawn(serve(listener))
+        .detach();
+    Ok(local)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write as _},
+        thread,
+    };
+
+    use super::*;
+    use crate::config::{AddrSelect, AddrSource, IpVersion, OnDelete};
+
+    fn host() -> HostConfig {
+        HostConfig {
+            name: "www".to_string(),
+            domain: "example.com".to_string(),
+            iface: "eth0".to_string(),
+            ip_version: IpVersion::V4,
+            select: AddrSelect::Primary,
+            source: AddrSource::Netlink,
+            on_delete: OnDelete::Ignore,
+        }
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let labels = record_labels(&host(), AddressFamily::Inet);
+        let mut registry = Registry::default();
+        registry.netlink_events.insert("add", 3);
+        registry.attempts.insert(labels.clone(), 2);
+        registry.failures.insert(labels, 1);
+
+        let out = registry.render();
+        assert!(out.contains("# TYPE netlink_ddns_netlink_events_total counter\n"));
+        assert!(out.contains("netlink_ddns_netlink_events_total{change=\"add\"} 3\n"));
+        assert!(out.contains("netlink_ddns_update_attempts_total{host=\"www.example.com\",type=\"A\"} 2\n"));
+        assert!(out.contains("netlink_ddns_update_failures_total{host=\"www.example.com\",type=\"A\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_published() {
+        let labels = record_labels(&host(), AddressFamily::Inet6);
+        let mut registry = Registry::default();
+        registry.published.insert(labels, "2001:db8::1".parse().unwrap());
+
+        let out = registry.render();
+        assert!(out.contains(
+            "netlink_ddns_published_address{host=\"www.example.com\",type=\"AAAA\",address=\"2001:db8::1\"} 1\n"));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut registry = Registry::default();
+        let hist = registry.latency.entry("get").or_default();
+        hist.observe(0.2);
+        hist.observe(3.0);
+
+        let out = registry.render();
+        let name = "netlink_ddns_provider_request_duration_seconds";
+        assert!(out.contains(&format!("{name}_bucket{{operation=\"get\",le=\"0.1\"}} 0\n")));
+        assert!(out.contains(&format!("{name}_bucket{{operation=\"get\",le=\"0.25\"}} 1\n")));
+        assert!(out.contains(&format!("{name}_bucket{{operation=\"get\",le=\"5\"}} 2\n")));
+        assert!(out.contains(&format!("{name}_bucket{{operation=\"get\",le=\"+Inf\"}} 2\n")));
+        assert!(out.contains(&format!("{name}_sum{{operation=\"get\"}} 3.2\n")));
+        assert!(out.contains(&format!("{name}_count{{operation=\"get\"}} 2\n")));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+
+    fn http_get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        compio::runtime::Runtime::new()?.block_on(async {
+            update_attempted(&host(), AddressFamily::Inet);
+            let addr = start("127.0.0.1:0".parse()?).await?;
+
+            let client = thread::spawn(move || (http_get(addr, "/metrics"), http_get(addr, "/")));
+            let (metrics, other) = loop {
+                if client.is_finished() {
+                    break client.join().unwrap();
+                }
+                compio::runtime::time::sleep(Duration::from_millis(10)).await;
+            };
+
+            assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(metrics.contains("netlink_ddns_update_attempts_total{host=\"www.example.com\",type=\"A\"}"));
+            assert!(other.starts_with("HTTP/1.1 404 Not Found\r\n"));
+            Ok(())
+        })
+    }
+}
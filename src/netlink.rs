@@ -15,29 +15,40 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 use std::{
     future,
-    net::{IpAddr, Ipv4Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use futures::{
-    channel::mpsc::{unbounded, UnboundedReceiver},
-    stream, SinkExt, StreamExt, TryStreamExt,
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    SinkExt, Stream, StreamExt, TryStreamExt,
 };
 use netlink_sys::{AsyncSocket, SocketAddr};
 use rtnetlink::{
-    constants::RTMGRP_IPV4_IFADDR,
+    constants::{
+        RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+    },
     new_connection_with_socket,
-    packet_core::NetlinkPayload,
+    packet_core::{NetlinkMessage, NetlinkPayload, NLM_F_REPLACE},
     packet_route::{
-        address::{AddressAttribute, AddressMessage},
+        address::{AddressAttribute, AddressFlags, AddressMessage, AddressScope},
+        link::{LinkAttribute, LinkFlags, LinkMessage},
+        route::{RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteType},
         AddressFamily, RouteNetlinkMessage,
     },
     sys::SmolSocket,
+    Handle,
 };
-use tracing::{debug, warn};
+use tracing::{debug, info, warn};
+
+use crate::pattern::IfacePattern;
+
+/// The interface name that follows whichever interface carries the default
+/// route.
+pub const AUTO_IFACE: &str = "auto";
 
 /// Represents the type of IP address change.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum ChangeType {
     /// An IP address was added to the interface
     Add,
@@ -45,133 +56,329 @@ pub enum ChangeType {
     Del,
 }
 
+/// An address assigned to an interface, along with the kernel metadata used
+/// to select between several addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IfAddr {
+    /// The local IPv4 or IPv6 address
+    pub ip: IpAddr,
+    /// The remote end of a point-to-point link (e.g. PPPoE or tun), if any
+    pub peer: Option<IpAddr>,
+    /// The prefix length of the attached network
+    pub prefix_len: u8,
+    /// The address label (IPv4 only); e.g. "eth0" or an alias like "eth0:1"
+    pub label: Option<String>,
+    /// The address scope
+    pub scope: AddressScope,
+    /// The address flags (secondary, deprecated, etc.)
+    pub flags: AddressFlags,
+}
+
+impl IfAddr {
+    /// Whether the kernel has marked this as a secondary address.
+    pub fn is_secondary(&self) -> bool {
+        self.flags.contains(AddressFlags::Secondary)
+    }
+}
+
 /// Represents a change in IP address on a network interface.
 #[derive(Debug)]
 pub struct IpAddrChange {
     /// The type of change (addition or deletion)
     pub ctype: ChangeType,
     /// The name of the network interface where the change occurred
-    #[allow(dead_code)]
     pub iface: String,
-    /// The IPv4 address that was added or removed
-    pub addr: Ipv4Addr,
+    /// The IPv4 or IPv6 address that was added or removed
+    pub addr: IfAddr,
+}
+
+/// The state of a monitored network interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    /// The interface is up and running
+    Up,
+    /// The interface exists but is down or has no carrier
+    Down,
+    /// The interface doesn't exist; e.g. a PPP or tun device that was torn down
+    Removed,
+}
+
+/// Represents a change in the state of a network interface.
+#[derive(Debug)]
+pub struct LinkChange {
+    /// The name of the network interface
+    pub iface: String,
+    /// The new state of the interface
+    pub state: LinkState,
+}
+
+/// An event on one of the monitored interfaces.
+#[derive(Debug)]
+pub enum IfEvent {
+    /// An address was added or removed
+    Addr(IpAddrChange),
+    /// The interface came up, went down, or was removed
+    Link(LinkChange),
+}
+
+/// A monitored interface and its last known state.
+#[derive(Debug)]
+struct Link {
+    name: String,
+    /// The kernel's index for the interface; this changes if the interface
+    /// is recreated, and is `None` while it doesn't exist.
+    index: Option<u32>,
+    state: LinkState,
+}
+
+/// A default route in the main table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DefaultRoute {
+    family: AddressFamily,
+    /// The output interface index
+    oif: u32,
+    /// The route metric; the lowest wins
+    priority: u32,
+    /// The preferred source address, if set
+    prefsrc: Option<IpAddr>,
+}
+
+/// An interface matching a monitored pattern.
+#[derive(Debug)]
+struct Matched {
+    index: u32,
+    name: String,
+    /// The usable addresses of the families being monitored
+    addrs: Vec<IpAddr>,
+}
+
+/// A monitored interface pattern and the interfaces currently matching it.
+#[derive(Debug)]
+struct PatternLinks {
+    /// The pattern as configured; e.g. "ppp*"
+    name: String,
+    pattern: IfacePattern,
+    links: Vec<Matched>,
+}
+
+impl PatternLinks {
+    /// The interface followed for `family`. If several matching interfaces
+    /// have a usable address the one with the lowest index, i.e. the
+    /// longest-lived, is chosen.
+    fn chosen(&self, family: AddressFamily) -> Option<u32> {
+        self.links.iter()
+            .filter(|link| link.addrs.iter().any(|ip| is_family(ip, family)))
+            .map(|link| link.index)
+            .min()
+    }
+
+    fn chosen_all(&self) -> [Option<u32>; 2] {
+        [AddressFamily::Inet, AddressFamily::Inet6]
+            .map(|family| self.chosen(family))
+    }
 }
 
-/// Retrieves the IPv4 address of a network interface.
+/// The monitored interfaces and, when following the default route, the
+/// current default routes.
+#[derive(Debug, Default)]
+struct Monitor {
+    links: Vec<Link>,
+    routes: Option<Vec<DefaultRoute>>,
+    patterns: Vec<PatternLinks>,
+}
+
+fn is_family(ip: &IpAddr, family: AddressFamily) -> bool {
+    (family == AddressFamily::Inet6) == ip.is_ipv6()
+}
+
+async fn get_link_index(handle: &Handle, ifname: &str) -> Result<u32> {
+    let link = handle
+        .link()
+        .get()
+        .match_name(ifname.to_string())
+        .execute()
+        .try_next().await
+        .with_context(|| format!("Failed to find interface {ifname}"))?
+        .with_context(|| format!("Failed to find interface {ifname}"))?;
+    Ok(link.header.index)
+}
+
+/// Retrieves the IPv4 or IPv6 addresses of a network interface.
 ///
-/// This function queries the system for the IPv4 address assigned to the specified
-/// network interface. It returns `None` if no IPv4 address is found, or an error
-/// if multiple IPv4 addresses are found or if the interface doesn't exist.
+/// This function queries the system for the addresses of the given family
+/// assigned to the specified network interface, in the order the kernel
+/// reports them. For IPv6 only global, non-temporary addresses are
+/// considered. Choosing between multiple addresses is left to the caller.
 ///
 /// # Arguments
 ///
 /// * `ifname` - The name of the network interface to query (e.g., "eth0", "wlan0")
+/// * `family` - The address family to query; `Inet` or `Inet6`
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing an `Option<Ipv4Addr>`:
-/// * `Ok(Some(addr))` - Successfully retrieved the IPv4 address
-/// * `Ok(None)` - No IPv4 address found for the interface
-/// * `Err(...)` - An error occurred (interface not found, multiple addresses, etc.)
+/// Returns a `Result` containing a `Vec<IfAddr>`, which is empty if no
+/// address of this family is found for the interface.
 ///
 /// # Errors
 ///
 /// This function will return an error if:
 /// * The specified interface doesn't exist
-/// * Multiple IPv4 addresses are found on the interface
 /// * Other system-level errors occur during the query
-pub(crate) async fn get_if_addr(ifname: &str) -> Result<Option<Ipv4Addr>> {
+pub(crate) async fn get_if_addrs(ifname: &str, family: AddressFamily) -> Result<Vec<IfAddr>> {
     let (connection, handle, _msgs) =
         new_connection_with_socket::<SmolSocket>()?;
 
     compio::runtime::spawn(connection)
         .detach();
 
-    let link = handle
-        .link()
-        .get()
-        .match_name(ifname.to_string())
-        .execute()
-        .try_next().await?
-        .context("Failed to find interface {ifname}")?;
+    let addrs = if ifname == AUTO_IFACE {
+        let routes = get_default_routes(&handle, family).await?;
+        let route = active_route(&routes, family)
+            .with_context(|| format!("No default {family:?} route"))?;
+        let mut addrs = get_link_addrs(&handle, route.oif, family).await?;
+        // The preferred source of the default route is the one to publish.
+        if route.prefsrc.is_some() {
+            addrs.sort_by_key(|addr| Some(addr.ip) != route.prefsrc);
+        }
+        addrs
+    } else if let Some(pattern) = IfacePattern::parse(ifname)? {
+        let mut addrs = Vec::new();
+        for (index, name) in get_matching_links(&handle, &pattern).await? {
+            addrs = get_link_addrs(&handle, index, family).await?;
+            if !addrs.is_empty() {
+                debug!("Using {name} for {ifname}");
+                break;
+            }
+        }
+        addrs
+    } else {
+        let index = get_link_index(&handle, ifname).await?;
+        get_link_addrs(&handle, index, family).await?
+    };
 
-    // Fetch link addresses
+    if addrs.is_empty() {
+        warn!("No {family:?} address found for interface {ifname}");
+    }
+    Ok(addrs)
+}
+
+async fn get_link_addrs(handle: &Handle, index: u32, family: AddressFamily) -> Result<Vec<IfAddr>> {
     let addrs = handle
         .address()
         .get()
-        .set_link_index_filter(link.header.index)
+        .set_link_index_filter(index)
         .execute()
-        // Extract attributes
-        .try_filter_map(|a| {
-            future::ready(if a.header.family == AddressFamily::Inet {
-                Ok(Some(a.attributes))
-            } else {
-                Ok(None)
-            })
-        })
-        .map_ok(|attrs| {
-            stream::iter(
-                attrs
-                    .into_iter()
-                    .map(Ok::<AddressAttribute, rtnetlink::Error>),
-            )
-        })
-        .try_flatten()
-        .try_collect::<Vec<AddressAttribute>>().await?
+        .try_filter(|a| future::ready(a.header.family == family))
+        .try_collect::<Vec<AddressMessage>>().await?
         // Extract relevant addresses
-        .into_iter()
-        .flat_map(|a| {
-            if let AddressAttribute::Address(addr) = a {
-                Some(addr)
-            } else {
-                None
-            }
+        .iter()
+        .flat_map(get_addr)
+        .collect::<Vec<IfAddr>>();
+    Ok(addrs)
+}
+
+/// The indexes and names of the interfaces matching `pattern`, in index
+/// order.
+async fn get_matching_links(handle: &Handle, pattern: &IfacePattern) -> Result<Vec<(u32, String)>> {
+    let mut links = handle
+        .link()
+        .get()
+        .execute()
+        .try_collect::<Vec<LinkMessage>>().await
+        .context("Failed to list interfaces")?
+        .iter()
+        .filter_map(|lmsg| {
+            link_name(lmsg)
+                .filter(|name| pattern.is_match(name))
+                .map(|name| (lmsg.header.index, name.to_string()))
         })
-        .collect::<Vec<IpAddr>>();
+        .collect::<Vec<_>>();
+    links.sort();
+    Ok(links)
+}
 
-    if addrs.is_empty() {
-        warn!("No IPv4 address found for interface {ifname}");
-        Ok(None)
-    } else if addrs.len() > 1 {
-        bail!("Multiple IPv4 addresses found on for interface {ifname}")
-    } else if let IpAddr::V4(ipaddr) = addrs[0] {
-        Ok(Some(ipaddr))
-    } else {
-        bail!("Found non-IPv4 address on {ifname}; this is an internal logic error")
+async fn get_default_routes(handle: &Handle, family: AddressFamily) -> Result<Vec<DefaultRoute>> {
+    let mut request = RouteMessage::default();
+    request.header.address_family = family;
+    let routes = handle
+        .route()
+        .get(request)
+        .execute()
+        .try_collect::<Vec<RouteMessage>>().await
+        .with_context(|| format!("Failed to read the {family:?} routing table"))?
+        .iter()
+        .filter_map(default_route)
+        .collect();
+    Ok(routes)
+}
+
+async fn find_link(handle: &Handle, ifname: &str) -> Option<LinkMessage> {
+    match handle.link().get().match_name(ifname.to_string()).execute().try_next().await {
+        Ok(link) => link,
+        Err(err) => {
+            debug!("Interface {ifname} not found: {err}");
+            None
+        }
     }
 }
 
-/// Creates a stream that monitors IPv4 address changes on a specific network interface.
+/// Creates a stream that monitors a set of network interfaces.
 ///
-/// This function sets up a netlink socket to listen for IPv4 address additions and deletions
-/// on the specified interface. It returns an unbounded receiver that will receive
-/// `IpAddrChange` notifications when addresses are added or removed.
+/// This function sets up a single netlink socket to listen for address
+/// additions and deletions of the given families on the specified
+/// interfaces, along with the interfaces coming up, going down, or being
+/// removed. It returns an unbounded receiver that will receive an `IfEvent`
+/// for each change.
+///
+/// The interfaces don't need to exist yet; they are picked up when they
+/// are created, and followed if they are removed and recreated.
+///
+/// If `ifnames` contains `AUTO_IFACE` the routing table is monitored too,
+/// and changes on whichever interface carries the default route are
+/// reported under that name. A change of default route is reported as
+/// `AUTO_IFACE` coming up, or as removed if there is no longer one.
+///
+/// Interface patterns (see `IfacePattern`) are handled the same way:
+/// changes on the matching interface being followed are reported under the
+/// pattern, and a switch to another interface as the pattern coming up.
 ///
 /// # Arguments
 ///
-/// * `ifname` - The name of the network interface to monitor (e.g., "eth0", "wlan0")
+/// * `ifnames` - The names of the network interfaces to monitor (e.g., "eth0", "wlan0")
+/// * `families` - The address families to monitor; `Inet` and/or `Inet6`
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing an `UnboundedReceiver<IpAddrChange>` that will receive
-/// notifications about IP address changes, or an error if the netlink connection fails.
+/// Returns a `Result` containing an `UnboundedReceiver<IfEvent>` that will receive
+/// notifications about interface changes, or an error if the netlink connection fails.
 ///
 /// # Example
 ///
 /// ```rust
-/// use netlink_ddns::netlink::ipv4_addr_stream;
+/// use netlink_ddns::netlink::iface_stream;
+/// use rtnetlink::packet_route::AddressFamily;
 ///
 /// # async fn example() -> anyhow::Result<()> {
-/// let stream = ipv4_addr_stream("eth0").await?;
+/// let stream = iface_stream(vec!["eth0".to_string()], &[AddressFamily::Inet]).await?;
 /// # Ok(())
 /// # }
 /// ```
-pub async fn ipv4_addr_stream(ifname: &'static str) -> Result<UnboundedReceiver<IpAddrChange>> {
-    let addr = SocketAddr::new(0, RTMGRP_IPV4_IFADDR);
+pub async fn iface_stream(ifnames: Vec<String>, families: &[AddressFamily]) -> Result<UnboundedReceiver<IfEvent>> {
+    let follow_route = ifnames.iter().any(|name| name == AUTO_IFACE);
+    let groups = families.iter()
+        .fold(RTMGRP_LINK, |groups, family| match family {
+            AddressFamily::Inet if follow_route => groups | RTMGRP_IPV4_IFADDR | RTMGRP_IPV4_ROUTE,
+            AddressFamily::Inet6 if follow_route => groups | RTMGRP_IPV6_IFADDR | RTMGRP_IPV6_ROUTE,
+            AddressFamily::Inet => groups | RTMGRP_IPV4_IFADDR,
+            AddressFamily::Inet6 => groups | RTMGRP_IPV6_IFADDR,
+            _ => groups,
+        });
+    let addr = SocketAddr::new(0, groups);
 
-    let (mut connection, _handle, mut nlmsgs) =
+    let (mut connection, handle, nlmsgs) =
         new_connection_with_socket::<SmolSocket>()?;
-    let (mut tx, rx) = unbounded();
+    let (tx, rx) = unbounded();
 
     connection
         .socket_mut()
@@ -181,47 +388,92 @@ pub async fn ipv4_addr_stream(ifname: &'static str) -> Result<UnboundedReceiver<
     compio::runtime::spawn(connection)
         .detach();
 
-    compio::runtime::spawn(async move {
-        while let Some((message, _)) = nlmsgs.next().await {
-            match message.payload {
-                NetlinkPayload::InnerMessage(msg) => {
-                    debug!("Got payload: {msg:?}");
-                    if let Some(m) = filter_msg(ifname, msg) {
-                        tx.send(m).await.unwrap();
-                    }
-                }
-                _ => {
-                    // According to https://docs.kernel.org/userspace-api/netlink/intro.html:
-                    //
-                    //   This is a unidirectional form of communication (kernel -> user)
-                    //   and does not involve any control messages like NLMSG_ERROR or NLMSG_DONE.
-                    //
-                    warn!("Unexpected netlink message: {message:?}");
+    let mut monitor = Monitor::default();
+    for name in ifnames {
+        if name == AUTO_IFACE {
+            let mut routes = Vec::new();
+            for family in families {
+                routes.extend(get_default_routes(&handle, *family).await?);
+            }
+            monitor.routes = Some(routes);
+            continue;
+        }
+        if let Some(pattern) = IfacePattern::parse(&name)? {
+            let mut links = Vec::new();
+            for (index, ifname) in get_matching_links(&handle, &pattern).await? {
+                let mut addrs = Vec::new();
+                for family in families {
+                    addrs.extend(get_link_addrs(&handle, index, *family).await?
+                                 .iter()
+                                 .map(|addr| addr.ip));
                 }
+                links.push(Matched { index, name: ifname, addrs });
             }
+            monitor.patterns.push(PatternLinks { name, pattern, links });
+            continue;
         }
-    })
-    .detach();
+        let link = match find_link(&handle, &name).await {
+            Some(lmsg) => Link { name, index: Some(lmsg.header.index), state: link_state(&lmsg) },
+            None => Link { name, index: None, state: LinkState::Removed },
+        };
+        monitor.links.push(link);
+    }
+
+    compio::runtime::spawn(forward_events(nlmsgs, monitor, tx))
+        .detach();
 
     Ok(rx)
 }
 
-fn is_our_if(ifname: &str, addr: &AddressMessage) -> bool {
-    addr.attributes.iter()
-        .find_map(|attr| {
+/// Whether an address belongs to the interface with this index.
+///
+/// The label isn't used here: it is only sent for IPv4, is truncated to 15
+/// characters, and is the alias name (e.g. "eth0:1") for aliased
+/// addresses. Selecting by label is done later, via `AddrSelect::Label`.
+fn is_our_if(index: u32, addr: &AddressMessage) -> bool {
+    addr.header.index == index
+}
+
+/// The addresses of one kind in an address message.
+fn attr_ips(amsg: &AddressMessage, local: bool) -> Vec<IpAddr> {
+    amsg.attributes.iter()
+        .filter_map(|attr| {
             match attr {
-                AddressAttribute::Label(l) => Some(l),
+                AddressAttribute::Local(ip) if local => Some(*ip),
+                AddressAttribute::Address(ip) if !local => Some(*ip),
                 _ => None,
             }
         })
-        .is_some_and(|nif| nif == ifname)
+        .collect()
+}
+
+/// The addresses that belong to us. On point-to-point links `IFA_ADDRESS`
+/// is the peer and `IFA_LOCAL` is ours; on other links they are the same,
+/// or `IFA_LOCAL` is absent (IPv6).
+fn local_ips(amsg: &AddressMessage) -> Vec<IpAddr> {
+    let locals = attr_ips(amsg, true);
+    if locals.is_empty() {
+        attr_ips(amsg, false)
+    } else {
+        locals
+    }
+}
+
+/// The remote address of a point-to-point link.
+fn get_peer(amsg: &AddressMessage) -> Option<IpAddr> {
+    let local = attr_ips(amsg, true);
+    let peer = attr_ips(amsg, false);
+    match (&local[..], &peer[..]) {
+        ([local], [peer]) if local != peer => Some(*peer),
+        _ => None,
+    }
 }
 
 fn get_ip(amsg: &AddressMessage) -> Option<Ipv4Addr> {
-    let v4s = amsg.attributes.iter()
-        .filter_map(|attr| {
-            match attr {
-                AddressAttribute::Address(IpAddr::V4(ip)) => Some(*ip),
+    let v4s = local_ips(amsg).into_iter()
+        .filter_map(|ip| {
+            match ip {
+                IpAddr::V4(ip) => Some(ip),
                 _ => None,
             }
         })
@@ -237,31 +489,419 @@ fn get_ip(amsg: &AddressMessage) -> Option<Ipv4Addr> {
     }
 }
 
-fn filter_msg(ifname: &str, msg: RouteNetlinkMessage) -> Option<IpAddrChange> {
+fn addr_flags(amsg: &AddressMessage) -> AddressFlags {
+    // The full flags are in the attribute; the header only holds the lower 8 bits.
+    amsg.attributes.iter()
+        .find_map(|attr| {
+            match attr {
+                AddressAttribute::Flags(flags) => Some(*flags),
+                _ => None,
+            }
+        })
+        .unwrap_or_else(|| AddressFlags::from_bits_retain(amsg.header.flags.bits() as u32))
+}
+
+fn get_ip6(amsg: &AddressMessage) -> Option<Ipv6Addr> {
+    if amsg.header.scope != AddressScope::Universe {
+        debug!("Skipping non-global IPv6 address");
+        return None;
+    }
+    // NOTE: For IPv6 the secondary flag is IFA_F_TEMPORARY
+    let unusable = AddressFlags::Secondary
+        | AddressFlags::Deprecated
+        | AddressFlags::Tentative
+        | AddressFlags::Dadfailed;
+    if addr_flags(amsg).intersects(unusable) {
+        debug!("Skipping temporary or unusable IPv6 address");
+        return None;
+    }
+
+    let v6s = local_ips(amsg).into_iter()
+        .filter_map(|ip| {
+            match ip {
+                IpAddr::V6(ip) => Some(ip),
+                _ => None,
+            }
+        })
+        .collect::<Vec<Ipv6Addr>>();
+
+    match v6s.len() {
+        0 => None,
+        1 => Some(v6s[0]),
+        _ => {
+            warn!("More that 1 IPv6 address found; not updating: {v6s:?}");
+            None
+        }
+    }
+}
+
+fn get_label(amsg: &AddressMessage) -> Option<String> {
+    amsg.attributes.iter()
+        .find_map(|attr| {
+            match attr {
+                AddressAttribute::Label(l) => Some(l.clone()),
+                _ => None,
+            }
+        })
+}
+
+fn get_addr(amsg: &AddressMessage) -> Option<IfAddr> {
+    let ip = match amsg.header.family {
+        AddressFamily::Inet6 => IpAddr::V6(get_ip6(amsg)?),
+        _ => IpAddr::V4(get_ip(amsg)?),
+    };
+    Some(IfAddr {
+        ip,
+        peer: get_peer(amsg),
+        prefix_len: amsg.header.prefix_len,
+        label: get_label(amsg),
+        scope: amsg.header.scope,
+        flags: addr_flags(amsg),
+    })
+}
+
+/// The names of the monitored interfaces an address belongs to.
+fn our_ifaces<'a>(monitor: &'a Monitor, amsg: &AddressMessage) -> Vec<&'a str> {
+    let mut ifaces = monitor.links.iter()
+        .filter(|link| link.index.is_some_and(|index| is_our_if(index, amsg)))
+        .map(|link| link.name.as_str())
+        .collect::<Vec<_>>();
+
+    if let Some(routes) = &monitor.routes
+        && let Some(route) = active_route(routes, amsg.header.family)
+        && is_our_if(route.oif, amsg)
+    {
+        // Only the preferred source is published if the route has one.
+        let preferred = route.prefsrc
+            .is_none_or(|src| local_ips(amsg).contains(&src));
+        if preferred {
+            ifaces.push(AUTO_IFACE);
+        }
+    }
+    ifaces
+}
+
+/// Parse a route message, returning it if it is a default route in the
+/// main table.
+fn default_route(rmsg: &RouteMessage) -> Option<DefaultRoute> {
+    let table = rmsg.attributes.iter()
+        .find_map(|attr| {
+            match attr {
+                RouteAttribute::Table(table) => Some(*table),
+                _ => None,
+            }
+        })
+        .unwrap_or(rmsg.header.table as u32);
+    if rmsg.header.destination_prefix_length != 0
+        || rmsg.header.kind != RouteType::Unicast
+        || table != RouteHeader::RT_TABLE_MAIN as u32
+    {
+        return None;
+    }
+
+    let mut route = DefaultRoute {
+        family: rmsg.header.address_family,
+        oif: 0,
+        priority: 0,
+        prefsrc: None,
+    };
+    for attr in &rmsg.attributes {
+        match attr {
+            RouteAttribute::Oif(oif) => route.oif = *oif,
+            RouteAttribute::Priority(priority) => route.priority = *priority,
+            RouteAttribute::PrefSource(RouteAddress::Inet(ip)) => route.prefsrc = Some(IpAddr::V4(*ip)),
+            RouteAttribute::PrefSource(RouteAddress::Inet6(ip)) => route.prefsrc = Some(IpAddr::V6(*ip)),
+            _ => {}
+        }
+    }
+    if route.oif == 0 {
+        debug!("Ignoring default route without a single output interface: {rmsg:?}");
+        return None;
+    }
+    Some(route)
+}
+
+/// The default route in use for `family`; the one with the lowest metric.
+fn active_route(routes: &[DefaultRoute], family: AddressFamily) -> Option<&DefaultRoute> {
+    routes.iter()
+        .filter(|route| route.family == family)
+        .min_by_key(|route| route.priority)
+}
+
+/// Record an added or deleted default route, returning an event if the
+/// route in use changed.
+///
+/// Routes are identified by family, metric and interface, so several routes
+/// with the same metric can be tracked. A route added with `replace` set
+/// (NLM_F_REPLACE) supersedes those with the same family and metric.
+fn update_routes(monitor: &mut Monitor, rmsg: &RouteMessage, added: bool, replace: bool) -> Vec<IfEvent> {
+    let (Some(routes), Some(route)) = (monitor.routes.as_mut(), default_route(rmsg)) else {
+        return Vec::new();
+    };
+    let active = |routes: &[DefaultRoute]| {
+        [AddressFamily::Inet, AddressFamily::Inet6]
+            .map(|family| active_route(routes, family).cloned())
+    };
+
+    let before = active(routes);
+    routes.retain(|r| {
+        r.family != route.family || r.priority != route.priority || (!replace && r.oif != route.oif)
+    });
+    if added {
+        routes.push(route);
+    }
+    let after = active(routes);
+    if before == after {
+        return Vec::new();
+    }
+
+    let state = if after.iter().any(Option::is_some) {
+        LinkState::Up
+    } else {
+        LinkState::Removed
+    };
+    info!("Default route changed: {after:?}");
+    vec![IfEvent::Link(LinkChange {
+        iface: AUTO_IFACE.to_string(),
+        state,
+    })]
+}
+
+fn link_state(lmsg: &LinkMessage) -> LinkState {
+    if lmsg.header.flags.contains(LinkFlags::Up | LinkFlags::Running) {
+        LinkState::Up
+    } else {
+        LinkState::Down
+    }
+}
+
+fn link_name(lmsg: &LinkMessage) -> Option<&str> {
+    lmsg.attributes.iter()
+        .find_map(|attr| {
+            match attr {
+                LinkAttribute::IfName(name) => Some(name.as_str()),
+                _ => None,
+            }
+        })
+}
+
+/// Forward interface events from the netlink subscription until either it
+/// or the receiving end is closed. Returning drops `nlmsgs`, which lets the
+/// netlink connection shut down.
+async fn forward_events(
+    mut nlmsgs: impl Stream<Item = (NetlinkMessage<RouteNetlinkMessage>, SocketAddr)> + Unpin,
+    mut monitor: Monitor,
+    mut tx: UnboundedSender<IfEvent>,
+) {
+    while let Some((message, _)) = nlmsgs.next().await {
+        let replace = message.header.flags & NLM_F_REPLACE != 0;
+        match message.payload {
+            NetlinkPayload::InnerMessage(msg) => {
+                debug!("Got payload: {msg:?}");
+                for m in filter_msg(&mut monitor, msg, replace) {
+                    if tx.send(m).await.is_err() {
+                        debug!("Interface event receiver closed; stopping netlink subscription");
+                        return;
+                    }
+                }
+            }
+            _ => {
+                // According to https://docs.kernel.org/userspace-api/netlink/intro.html:
+                //
+                //   This is a unidirectional form of communication (kernel -> user)
+                //   and does not involve any control messages like NLMSG_ERROR or NLMSG_DONE.
+                //
+                warn!("Unexpected netlink message: {message:?}");
+            }
+        }
+    }
+}
+
+fn set_state(link: &mut Link, state: LinkState, events: &mut Vec<IfEvent>) {
+    if link.state != state {
+        link.state = state;
+        events.push(IfEvent::Link(LinkChange {
+            iface: link.name.clone(),
+            state,
+        }));
+    }
+}
+
+/// Record the new state of the monitored links, returning an event for
+/// each one that changed. The kernel sends link messages for many minor
+/// changes, so most of these are ignored.
+///
+/// Links are followed by name: if a monitored interface is renamed it is
+/// treated as removed, and an interface renamed to a monitored name is
+/// treated as created.
+fn update_links(links: &mut [Link], lmsg: &LinkMessage, state: LinkState) -> Vec<IfEvent> {
+    let mut events = Vec::new();
+    let Some(name) = link_name(lmsg) else {
+        return events;
+    };
+    let index = lmsg.header.index;
+
+    if let Some(link) = links.iter_mut()
+        .find(|link| link.index == Some(index) && link.name != name)
+    {
+        info!("Interface {} was renamed to {name}", link.name);
+        link.index = None;
+        set_state(link, LinkState::Removed, &mut events);
+    }
+
+    if let Some(link) = links.iter_mut()
+        .find(|link| link.name == name)
+    {
+        link.index = (state != LinkState::Removed).then_some(index);
+        set_state(link, state, &mut events);
+    }
+    events
+}
+
+/// The event reported when a pattern switches to another interface, or
+/// runs out of them.
+fn pattern_switched(patlinks: &PatternLinks, before: [Option<u32>; 2]) -> Option<IfEvent> {
+    let after = patlinks.chosen_all();
+    if before == after {
+        return None;
+    }
+    let names = after.map(|index| {
+        patlinks.links.iter()
+            .find(|link| Some(link.index) == index)
+            .map_or("none", |link| link.name.as_str())
+    });
+    info!("{} now follows {} (IPv4) and {} (IPv6)", patlinks.name, names[0], names[1]);
+
+    let state = if after.iter().any(Option::is_some) {
+        LinkState::Up
+    } else {
+        LinkState::Removed
+    };
+    Some(IfEvent::Link(LinkChange {
+        iface: patlinks.name.clone(),
+        state,
+    }))
+}
+
+/// Record an address change on an interface matching a pattern, returning
+/// the events to report for the pattern.
+fn update_pattern_addrs(patlinks: &mut PatternLinks, amsg: &AddressMessage, ctype: ChangeType) -> Vec<IfEvent> {
+    let index = amsg.header.index;
+    let ips = local_ips(amsg);
+    let [ip] = ips[..] else {
+        return Vec::new();
+    };
+    let family = amsg.header.family;
+    let before = patlinks.chosen(family);
+    let before_all = patlinks.chosen_all();
+
+    let Some(link) = patlinks.links.iter_mut().find(|link| link.index == index) else {
+        return Vec::new();
+    };
+    let addr = get_addr(amsg);
+    // An address that is no longer usable (e.g. deprecated) is dropped too.
+    link.addrs.retain(|a| *a != ip);
+    if matches!(ctype, ChangeType::Add) && addr.is_some() {
+        link.addrs.push(ip);
+    }
+
+    let mut events = Vec::new();
+    let after = patlinks.chosen(family);
+    let addr_event = |addr| IfEvent::Addr(IpAddrChange {
+        ctype,
+        iface: patlinks.name.clone(),
+        addr,
+    });
+    if before == after {
+        if after == Some(index) && let Some(addr) = addr {
+            events.push(addr_event(addr));
+        }
+    } else {
+        // The addresses of the new interface are read afresh, but the old
+        // one losing its address needs reporting.
+        if before == Some(index) && matches!(ctype, ChangeType::Del) && let Some(addr) = addr {
+            events.push(addr_event(addr));
+        }
+        events.extend(pattern_switched(patlinks, before_all));
+    }
+    events
+}
+
+/// Record a link appearing, disappearing or being renamed, returning an
+/// event for each pattern that switched to another interface as a result.
+fn update_pattern_links(patterns: &mut [PatternLinks], lmsg: &LinkMessage, removed: bool) -> Vec<IfEvent> {
+    let Some(name) = link_name(lmsg) else {
+        return Vec::new();
+    };
+    let index = lmsg.header.index;
+
+    let mut events = Vec::new();
+    for patlinks in patterns {
+        let before = patlinks.chosen_all();
+        let matches = !removed && patlinks.pattern.is_match(name);
+        let known = patlinks.links.iter().position(|link| link.index == index);
+        match (known, matches) {
+            (Some(i), false) => {
+                patlinks.links.remove(i);
+            }
+            (Some(i), true) => patlinks.links[i].name = name.to_string(),
+            (None, true) => {
+                info!("Interface {name} matches {}", patlinks.name);
+                patlinks.links.push(Matched { index, name: name.to_string(), addrs: Vec::new() });
+            }
+            (None, false) => {}
+        }
+        events.extend(pattern_switched(patlinks, before));
+    }
+    events
+}
+
+fn addr_events(monitor: &mut Monitor, amsg: &AddressMessage, ctype: ChangeType) -> Vec<IfEvent> {
+    let mut events = Vec::new();
+    if let Some(addr) = get_addr(amsg) {
+        events.extend(our_ifaces(monitor, amsg).into_iter()
+            .map(|ifname| IfEvent::Addr(IpAddrChange {
+                ctype,
+                iface: ifname.to_owned(),
+                addr: addr.clone(),
+            })));
+    }
+    for patlinks in &mut monitor.patterns {
+        events.extend(update_pattern_addrs(patlinks, amsg, ctype));
+    }
+    events
+}
+
+/// Process a message, returning the events to report; `replace` is set for
+/// messages carrying NLM_F_REPLACE.
+fn filter_msg(monitor: &mut Monitor, msg: RouteNetlinkMessage, replace: bool) -> Vec<IfEvent> {
     match msg {
-        RouteNetlinkMessage::NewAddress(ref amsg)
-            if is_our_if(ifname, amsg) =>
-        {
-            get_ip(amsg)
-                .map(|addr| IpAddrChange {
-                    ctype: ChangeType::Add,
-                    iface: ifname.to_owned(),
-                    addr,
-                })
+        RouteNetlinkMessage::NewAddress(ref amsg) => {
+            addr_events(monitor, amsg, ChangeType::Add)
+        }
+        RouteNetlinkMessage::DelAddress(ref amsg) => {
+            addr_events(monitor, amsg, ChangeType::Del)
+        }
+        RouteNetlinkMessage::NewLink(ref lmsg) => {
+            let state = link_state(lmsg);
+            let mut events = update_links(&mut monitor.links, lmsg, state);
+            events.extend(update_pattern_links(&mut monitor.patterns, lmsg, false));
+            events
         }
-        RouteNetlinkMessage::DelAddress(ref amsg)
-            if is_our_if(ifname, amsg) =>
-        {
-            get_ip(amsg)
-                .map(|addr| IpAddrChange {
-                    ctype: ChangeType::Del,
-                    iface: ifname.to_owned(),
-                    addr,
-                })
+        RouteNetlinkMessage::DelLink(ref lmsg) => {
+            let mut events = update_links(&mut monitor.links, lmsg, LinkState::Removed);
+            events.extend(update_pattern_links(&mut monitor.patterns, lmsg, true));
+            events
+        }
+        RouteNetlinkMessage::NewRoute(ref rmsg) => {
+            update_routes(monitor, rmsg, true, replace)
+        }
+        RouteNetlinkMessage::DelRoute(ref rmsg) => {
+            update_routes(monitor, rmsg, false, false)
         }
         _ => {
             warn!("Unexpected RouteNetlinkMessage: {msg:?}");
-            None
+            Vec::new()
         }
     }
 }
@@ -270,9 +910,13 @@ fn filter_msg(ifname: &str, msg: RouteNetlinkMessage) -> Option<IpAddrChange> {
 mod tests {
     use super::*;
     use async_fs::read_to_string;
+    use rtnetlink::packet_route::address::AddressHeaderFlags;
     use tracing_test::traced_test;
     use rtnetlink::packet_route::address::{AddressAttribute, AddressMessage};
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
 
     #[compio::test]
     #[traced_test]
@@ -287,69 +931,55 @@ mod tests {
             .take(1)
             .collect::<String>();
 
-        let _ip = get_if_addr(&ifname).await?;
+        let _ips = get_if_addrs(&ifname, AddressFamily::Inet).await?;
 
         Ok(())
     }
 
     #[test]
     fn test_is_our_if_matches() {
-        let ifname = "eth0";
-
         let mut addr = AddressMessage::default();
+        addr.header.index = 2;
         addr.attributes = vec![
             AddressAttribute::Label("eth0".to_string()),
         ];
 
-        assert!(is_our_if(ifname, &addr));
+        assert!(is_our_if(2, &addr));
     }
 
     #[test]
     fn test_is_our_if_no_match() {
-        let ifname = "eth0";
-
         let mut addr = AddressMessage::default();
+        addr.header.index = 3;
         addr.attributes = vec![
-            AddressAttribute::Label("wlan0".to_string()),
+            AddressAttribute::Label("eth0".to_string()),
         ];
 
-        assert!(!is_our_if(ifname, &addr));
+        assert!(!is_our_if(2, &addr));
     }
 
     #[test]
-    fn test_is_our_if_no_label() {
-        let ifname = "eth0";
-
+    fn test_is_our_if_alias() {
         let mut addr = AddressMessage::default();
+        addr.header.index = 2;
         addr.attributes = vec![
             AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+            AddressAttribute::Label("eth0:1".to_string()),
         ];
 
-        assert!(!is_our_if(ifname, &addr));
+        assert!(is_our_if(2, &addr));
     }
 
     #[test]
-    fn test_is_our_if_empty_attributes() {
-        let ifname = "eth0";
-
-        let mut addr = AddressMessage::default();
-        addr.attributes = vec![];
-
-        assert!(!is_our_if(ifname, &addr));
-    }
-
-    #[test]
-    fn test_is_our_if_multiple_attributes() {
-        let ifname = "eth0";
-
+    fn test_is_our_if_no_label() {
         let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet6;
+        addr.header.index = 2;
         addr.attributes = vec![
-            AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
-            AddressAttribute::Label("eth0".to_string()),
-            AddressAttribute::Local(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+            AddressAttribute::Address(IpAddr::V6("2001:db8::1".parse().unwrap())),
         ];
 
-        assert!(is_our_if(ifname, &addr));
+        assert!(is_our_if(2, &addr));
     }
 
     #[test]
@@ -369,13 +999,14 @@ mod tests {
     #[test]
     fn test_get_ip_with_no_address_attribute() {
         let mut addr = AddressMessage::default();
+        let expected_ip = Ipv4Addr::new(192, 168, 1, 1);
         addr.attributes = vec![
             AddressAttribute::Label("eth0".to_string()),
-            AddressAttribute::Local(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+            AddressAttribute::Local(IpAddr::V4(expected_ip)),
         ];
 
         let result = get_ip(&addr);
-        assert_eq!(result, None);
+        assert_eq!(result, Some(expected_ip));
     }
 
     #[test]
@@ -406,11 +1037,594 @@ mod tests {
         addr.attributes = vec![
             AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
             AddressAttribute::Address(IpAddr::V4(expected_ip)),
+        ];
+
+        // Ambiguous, so nothing is returned
+        let result = get_ip(&addr);
+        assert_eq!(result, None);
+
+        // IFA_LOCAL takes precedence
+        addr.attributes.push(AddressAttribute::Local(IpAddr::V4(expected_ip)));
+        let result = get_ip(&addr);
+        assert_eq!(result, Some(expected_ip));
+    }
+
+    #[test]
+    fn test_get_ip_multiple_local_addresses() {
+        let mut addr = AddressMessage::default();
+        addr.attributes = vec![
+            AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))),
             AddressAttribute::Local(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+            AddressAttribute::Local(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2))),
         ];
 
-        // Should return the first IPv4 address found
         let result = get_ip(&addr);
         assert_eq!(result, None);
     }
+
+    /// An address on a PPPoE link, as reported by `ip addr` as
+    /// `inet 100.70.1.2 peer 10.64.64.64/32`.
+    fn ppp_msg() -> AddressMessage {
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.index = 7;
+        addr.header.prefix_len = 32;
+        addr.attributes = vec![
+            AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(10, 64, 64, 64))),
+            AddressAttribute::Local(IpAddr::V4(Ipv4Addr::new(100, 70, 1, 2))),
+            AddressAttribute::Label("ppp0".to_string()),
+        ];
+        addr
+    }
+
+    #[test]
+    fn test_get_ip_point_to_point() {
+        let addr = ppp_msg();
+        assert_eq!(get_ip(&addr), Some(Ipv4Addr::new(100, 70, 1, 2)));
+
+        let ifaddr = get_addr(&addr).expect("Should have an address");
+        assert_eq!(ifaddr.ip, IpAddr::V4(Ipv4Addr::new(100, 70, 1, 2)));
+        assert_eq!(ifaddr.peer, Some(IpAddr::V4(Ipv4Addr::new(10, 64, 64, 64))));
+    }
+
+    #[test]
+    fn test_get_peer_broadcast_link() {
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let mut addr = AddressMessage::default();
+        addr.attributes = vec![
+            AddressAttribute::Address(ip),
+            AddressAttribute::Local(ip),
+        ];
+
+        assert_eq!(get_peer(&addr), None);
+        assert_eq!(get_addr(&addr).and_then(|a| a.peer), None);
+    }
+
+    fn v6_msg(scope: AddressScope, attributes: Vec<AddressAttribute>) -> AddressMessage {
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet6;
+        addr.header.index = 2;
+        addr.header.scope = scope;
+        addr.attributes = attributes;
+        addr
+    }
+
+    #[test]
+    fn test_get_ip6_global_address() {
+        let expected_ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V6(expected_ip)),
+            AddressAttribute::Flags(AddressFlags::Permanent),
+        ]);
+
+        assert_eq!(get_ip6(&addr), Some(expected_ip));
+        assert_eq!(get_addr(&addr).map(|a| a.ip), Some(IpAddr::V6(expected_ip)));
+    }
+
+    #[test]
+    fn test_get_ip6_point_to_point() {
+        let local: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let peer: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V6(peer)),
+            AddressAttribute::Local(IpAddr::V6(local)),
+        ]);
+
+        assert_eq!(get_ip6(&addr), Some(local));
+        assert_eq!(get_addr(&addr).and_then(|a| a.peer), Some(IpAddr::V6(peer)));
+    }
+
+    #[test]
+    fn test_get_ip6_link_local() {
+        let addr = v6_msg(AddressScope::Link, vec![
+            AddressAttribute::Address(IpAddr::V6("fe80::1".parse().unwrap())),
+        ]);
+
+        assert_eq!(get_ip6(&addr), None);
+    }
+
+    #[test]
+    fn test_get_ip6_temporary() {
+        let addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V6("2001:db8::2".parse().unwrap())),
+            AddressAttribute::Flags(AddressFlags::Secondary),
+        ]);
+
+        assert_eq!(get_ip6(&addr), None);
+    }
+
+    #[test]
+    fn test_get_ip6_ignores_ipv4() {
+        let addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))),
+        ]);
+
+        assert_eq!(get_ip6(&addr), None);
+    }
+
+    fn links(ifaces: &[(&str, u32)]) -> Monitor {
+        let links = ifaces.iter()
+            .map(|(name, index)| Link {
+                name: name.to_string(),
+                index: Some(*index),
+                state: LinkState::Up,
+            })
+            .collect();
+        Monitor { links, ..Default::default() }
+    }
+
+    fn addr_change(events: Vec<IfEvent>) -> Option<IpAddrChange> {
+        assert!(events.len() <= 1, "Unexpected events: {events:?}");
+        match events.into_iter().next()? {
+            IfEvent::Addr(change) => Some(change),
+            IfEvent::Link(link) => panic!("Unexpected link event: {link:?}"),
+        }
+    }
+
+    fn link_msg(name: &str, index: u32, flags: LinkFlags) -> LinkMessage {
+        let mut lmsg = LinkMessage::default();
+        lmsg.header.index = index;
+        lmsg.header.flags = flags;
+        lmsg.attributes = vec![
+            LinkAttribute::IfName(name.to_string()),
+        ];
+        lmsg
+    }
+
+    #[test]
+    fn test_filter_msg_v6_by_index() {
+        let mut ifaces = links(&[("eth0", 2)]);
+        let ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V6(ip)),
+        ]);
+
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr.clone()), false))
+            .expect("Should match on index");
+        assert!(matches!(change.ctype, ChangeType::Add));
+        assert_eq!(change.addr.ip, IpAddr::V6(ip));
+
+        let mut ifaces = links(&[("eth0", 3)]);
+        assert!(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr), false).is_empty());
+    }
+
+    #[test]
+    fn test_filter_msg_multiple_ifaces() {
+        let mut ifaces = links(&[("wan0", 2), ("wan1", 3)]);
+        let ip = Ipv4Addr::new(203, 0, 113, 7);
+
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.index = 3;
+        addr.attributes = vec![
+            AddressAttribute::Label("wan1".to_string()),
+            AddressAttribute::Address(IpAddr::V4(ip)),
+        ];
+
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::DelAddress(addr.clone()), false))
+            .expect("Should match wan1");
+        assert!(matches!(change.ctype, ChangeType::Del));
+        assert_eq!(change.iface, "wan1");
+        assert_eq!(change.addr.ip, IpAddr::V4(ip));
+
+        addr.header.index = 4;
+        assert!(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr), false).is_empty());
+    }
+
+    #[test]
+    fn test_filter_msg_point_to_point() {
+        let mut ifaces = links(&[("ppp0", 7)]);
+
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(ppp_msg()), false))
+            .expect("Should match ppp0");
+        assert_eq!(change.iface, "ppp0");
+        assert_eq!(change.addr.ip, IpAddr::V4(Ipv4Addr::new(100, 70, 1, 2)));
+        assert_eq!(change.addr.peer, Some(IpAddr::V4(Ipv4Addr::new(10, 64, 64, 64))));
+    }
+
+    #[test]
+    fn test_link_state_changes() {
+        let mut ifaces = links(&[("ppp0", 5)]);
+        let up = LinkFlags::Up | LinkFlags::Running;
+
+        let link = |events: Vec<IfEvent>| {
+            assert!(events.len() <= 1, "Unexpected events: {events:?}");
+            match events.into_iter().next() {
+                Some(IfEvent::Link(change)) => Some((change.iface, change.state)),
+                None => None,
+                Some(event) => panic!("Unexpected event: {event:?}"),
+            }
+        };
+
+        // Already up; stats and other updates are ignored.
+        let msg = link_msg("ppp0", 5, up);
+        assert_eq!(link(filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false)), None);
+
+        let msg = link_msg("ppp0", 5, LinkFlags::Up);
+        assert_eq!(link(filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false)),
+                   Some(("ppp0".to_string(), LinkState::Down)));
+
+        let msg = link_msg("ppp0", 5, LinkFlags::Up);
+        assert_eq!(link(filter_msg(&mut ifaces, RouteNetlinkMessage::DelLink(msg), false)),
+                   Some(("ppp0".to_string(), LinkState::Removed)));
+        assert_eq!(ifaces.links[0].index, None);
+
+        // Other interfaces are ignored.
+        let msg = link_msg("ppp1", 6, up);
+        assert_eq!(link(filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false)), None);
+    }
+
+    #[test]
+    fn test_link_recreated() {
+        let mut ifaces = links(&[("tun0", 4)]);
+        ifaces.links[0].index = None;
+        ifaces.links[0].state = LinkState::Removed;
+
+        let ip: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let mut addr = v6_msg(AddressScope::Universe, vec![
+            AddressAttribute::Address(IpAddr::V6(ip)),
+        ]);
+        addr.header.index = 9;
+        assert!(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr.clone()), false).is_empty());
+
+        let msg = link_msg("tun0", 9, LinkFlags::Up | LinkFlags::Running);
+        let events = filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false);
+        assert!(matches!(events[..], [IfEvent::Link(LinkChange { state: LinkState::Up, .. })]));
+        assert_eq!(ifaces.links[0].index, Some(9));
+
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr), false))
+            .expect("Should match the new index");
+        assert_eq!(change.iface, "tun0");
+    }
+
+    #[test]
+    fn test_link_renamed() {
+        let mut ifaces = links(&[("wan0", 2), ("wan1", 3)]);
+        let up = LinkFlags::Up | LinkFlags::Running;
+
+        // wan0 is renamed away, then wan1 takes its name.
+        let msg = link_msg("old0", 2, up);
+        let events = filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false);
+        assert!(matches!(&events[..], [IfEvent::Link(LinkChange { iface, state: LinkState::Removed })]
+                         if iface == "wan0"));
+        assert_eq!(ifaces.links[0].index, None);
+
+        let msg = link_msg("wan0", 3, up);
+        let events = filter_msg(&mut ifaces, RouteNetlinkMessage::NewLink(msg), false);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], IfEvent::Link(LinkChange { iface, state: LinkState::Removed })
+                         if iface == "wan1"));
+        assert!(matches!(&events[1], IfEvent::Link(LinkChange { iface, state: LinkState::Up })
+                         if iface == "wan0"));
+        assert_eq!(ifaces.links[0].index, Some(3));
+        assert_eq!(ifaces.links[1].index, None);
+
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.index = 3;
+        addr.attributes = vec![
+            // The label isn't updated until the address is re-added.
+            AddressAttribute::Label("wan1".to_string()),
+            AddressAttribute::Address(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))),
+        ];
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr), false))
+            .expect("Should match on index");
+        assert_eq!(change.iface, "wan0");
+    }
+
+    fn route_msg(oif: u32, priority: u32, prefsrc: Option<Ipv4Addr>) -> RouteMessage {
+        let mut rmsg = RouteMessage::default();
+        rmsg.header.address_family = AddressFamily::Inet;
+        rmsg.header.table = RouteHeader::RT_TABLE_MAIN;
+        rmsg.header.kind = RouteType::Unicast;
+        rmsg.attributes = vec![
+            RouteAttribute::Gateway(RouteAddress::Inet(Ipv4Addr::new(192, 168, 1, 1))),
+            RouteAttribute::Oif(oif),
+            RouteAttribute::Priority(priority),
+        ];
+        if let Some(ip) = prefsrc {
+            rmsg.attributes.push(RouteAttribute::PrefSource(RouteAddress::Inet(ip)));
+        }
+        rmsg
+    }
+
+    fn v4_msg(index: u32, ip: Ipv4Addr) -> AddressMessage {
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.index = index;
+        addr.attributes = vec![
+            AddressAttribute::Address(IpAddr::V4(ip)),
+        ];
+        addr
+    }
+
+    #[test]
+    fn test_default_route() {
+        let route = default_route(&route_msg(2, 100, None)).expect("Should be a default route");
+        assert_eq!(route.oif, 2);
+        assert_eq!(route.priority, 100);
+        assert_eq!(route.prefsrc, None);
+
+        let mut rmsg = route_msg(2, 100, None);
+        rmsg.header.destination_prefix_length = 24;
+        assert_eq!(default_route(&rmsg), None);
+
+        let mut rmsg = route_msg(2, 100, None);
+        rmsg.header.table = 100;
+        assert_eq!(default_route(&rmsg), None);
+
+        let mut rmsg = route_msg(2, 100, None);
+        rmsg.header.kind = RouteType::Unreachable;
+        assert_eq!(default_route(&rmsg), None);
+    }
+
+    #[test]
+    fn test_follow_default_route() {
+        let mut monitor = Monitor { routes: Some(Vec::new()), ..Default::default() };
+        let fibre = Ipv4Addr::new(203, 0, 113, 7);
+        let lte = Ipv4Addr::new(100, 64, 0, 9);
+
+        let auto = |events: Vec<IfEvent>| {
+            assert!(events.len() <= 1, "Unexpected events: {events:?}");
+            match events.into_iter().next() {
+                Some(IfEvent::Link(LinkChange { iface, state })) if iface == AUTO_IFACE => Some(state),
+                None => None,
+                Some(event) => panic!("Unexpected event: {event:?}"),
+            }
+        };
+
+        // Fibre on ifindex 2 is preferred over LTE on 3.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewRoute(route_msg(2, 100, None)), false);
+        assert_eq!(auto(events), Some(LinkState::Up));
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewRoute(route_msg(3, 200, None)), false);
+        assert_eq!(auto(events), None);
+
+        let change = addr_change(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(2, fibre)), false))
+            .expect("Should follow the fibre link");
+        assert_eq!(change.iface, AUTO_IFACE);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(3, lte)), false).is_empty());
+
+        // Fail over to LTE.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelRoute(route_msg(2, 100, None)), false);
+        assert_eq!(auto(events), Some(LinkState::Up));
+        let change = addr_change(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(3, lte)), false))
+            .expect("Should follow the LTE link");
+        assert_eq!(change.addr.ip, IpAddr::V4(lte));
+
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelRoute(route_msg(3, 200, None)), false);
+        assert_eq!(auto(events), Some(LinkState::Removed));
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(3, lte)), false).is_empty());
+    }
+
+    #[test]
+    fn test_same_metric_routes() {
+        let mut monitor = Monitor { routes: Some(Vec::new()), ..Default::default() };
+        let oifs = |monitor: &Monitor| monitor.routes.as_ref().unwrap().iter()
+            .map(|r| r.oif)
+            .collect::<Vec<_>>();
+        let auto_state = |events: Vec<IfEvent>| match events.as_slice() {
+            [IfEvent::Link(LinkChange { state, .. })] => Some(*state),
+            [] => None,
+            _ => panic!("Unexpected events: {events:?}"),
+        };
+
+        // Two uplinks with the same metric are both tracked.
+        update_routes(&mut monitor, &route_msg(2, 100, None), true, false);
+        update_routes(&mut monitor, &route_msg(3, 100, None), true, false);
+        assert_eq!(oifs(&monitor), vec![2, 3]);
+
+        // Deleting one leaves the other in use.
+        let events = update_routes(&mut monitor, &route_msg(2, 100, None), false, false);
+        assert_eq!(auto_state(events), Some(LinkState::Up));
+        assert_eq!(oifs(&monitor), vec![3]);
+        assert_eq!(active_route(monitor.routes.as_ref().unwrap(), AddressFamily::Inet).map(|r| r.oif), Some(3));
+
+        // A replacement supersedes the route with the same metric.
+        update_routes(&mut monitor, &route_msg(4, 100, None), true, true);
+        assert_eq!(oifs(&monitor), vec![4]);
+
+        let events = update_routes(&mut monitor, &route_msg(4, 100, None), false, false);
+        assert_eq!(auto_state(events), Some(LinkState::Removed));
+    }
+
+    #[test]
+    fn test_default_route_prefsrc() {
+        let preferred = Ipv4Addr::new(203, 0, 113, 7);
+        let mut monitor = links(&[("eth0", 2)]);
+        monitor.routes = Some(Vec::new());
+
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewRoute(route_msg(2, 0, Some(preferred))), false);
+        assert_eq!(events.len(), 1);
+
+        // Other addresses on the interface are only reported for the named interface.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(2, Ipv4Addr::new(203, 0, 113, 8))), false);
+        let ifaces = events.iter()
+            .map(|event| match event {
+                IfEvent::Addr(change) => change.iface.as_str(),
+                IfEvent::Link(link) => panic!("Unexpected link event: {link:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ifaces, vec!["eth0"]);
+
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(2, preferred)), false);
+        assert_eq!(events.len(), 2);
+    }
+
+    fn ppp_monitor(links: Vec<Matched>) -> Monitor {
+        let patlinks = PatternLinks {
+            name: "ppp*".to_string(),
+            pattern: IfacePattern::parse("ppp*").unwrap().unwrap(),
+            links,
+        };
+        Monitor { patterns: vec![patlinks], ..Default::default() }
+    }
+
+    /// Summarise events as (iface, description) pairs.
+    fn summary(events: Vec<IfEvent>) -> Vec<(String, String)> {
+        events.into_iter()
+            .map(|event| match event {
+                IfEvent::Addr(change) => (change.iface, format!("{:?} {}", change.ctype, change.addr.ip)),
+                IfEvent::Link(change) => (change.iface, format!("{:?}", change.state)),
+            })
+            .collect()
+    }
+
+    fn event(iface: &str, what: &str) -> (String, String) {
+        (iface.to_string(), what.to_string())
+    }
+
+    #[test]
+    fn test_pattern_reconnect() {
+        let old = Ipv4Addr::new(100, 70, 1, 2);
+        let new = Ipv4Addr::new(100, 70, 9, 9);
+        let mut monitor = ppp_monitor(vec![
+            Matched { index: 5, name: "ppp0".to_string(), addrs: vec![IpAddr::V4(old)] },
+        ]);
+
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelAddress(v4_msg(5, old)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Del 100.70.1.2"), event("ppp*", "Removed")]);
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelLink(link_msg("ppp0", 5, LinkFlags::empty())), false);
+        assert!(events.is_empty());
+
+        // The replacement comes up as ppp1.
+        let msg = link_msg("ppp1", 6, LinkFlags::Up | LinkFlags::Running);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewLink(msg), false).is_empty());
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(6, new)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Up")]);
+
+        // Further changes on ppp1 are reported as usual.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelAddress(v4_msg(6, new)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Del 100.70.9.9"), event("ppp*", "Removed")]);
+
+        // Non-matching interfaces are ignored.
+        let msg = link_msg("eth1", 7, LinkFlags::Up | LinkFlags::Running);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewLink(msg), false).is_empty());
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(7, new)), false).is_empty());
+    }
+
+    #[test]
+    fn test_pattern_tie_break() {
+        let first = Ipv4Addr::new(100, 70, 1, 2);
+        let second = Ipv4Addr::new(100, 70, 9, 9);
+        let mut monitor = ppp_monitor(vec![
+            Matched { index: 6, name: "ppp1".to_string(), addrs: vec![IpAddr::V4(second)] },
+            Matched { index: 5, name: "ppp0".to_string(), addrs: Vec::new() },
+        ]);
+        assert_eq!(monitor.patterns[0].chosen(AddressFamily::Inet), Some(6));
+
+        // The lowest index wins once it has an address.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(5, first)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Up")]);
+        assert_eq!(monitor.patterns[0].chosen(AddressFamily::Inet), Some(5));
+
+        // Changes on the other interface are ignored.
+        let other = Ipv4Addr::new(100, 70, 9, 10);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(6, other)), false).is_empty());
+
+        // ...until the chosen interface loses its address.
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::DelAddress(v4_msg(5, first)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Del 100.70.1.2"), event("ppp*", "Up")]);
+        assert_eq!(monitor.patterns[0].chosen(AddressFamily::Inet), Some(6));
+    }
+
+    #[test]
+    fn test_pattern_rename() {
+        let ip = Ipv4Addr::new(100, 70, 1, 2);
+        let mut monitor = ppp_monitor(Vec::new());
+        let up = LinkFlags::Up | LinkFlags::Running;
+
+        let msg = link_msg("tmp0", 5, up);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewLink(msg), false).is_empty());
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(5, ip)), false).is_empty());
+
+        // Renamed into the pattern; addresses are picked up from the next event.
+        let msg = link_msg("ppp0", 5, up);
+        assert!(filter_msg(&mut monitor, RouteNetlinkMessage::NewLink(msg), false).is_empty());
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewAddress(v4_msg(5, ip)), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Up")]);
+
+        // Renamed out of it.
+        let msg = link_msg("old0", 5, up);
+        let events = filter_msg(&mut monitor, RouteNetlinkMessage::NewLink(msg), false);
+        assert_eq!(summary(events), vec![event("ppp*", "Removed")]);
+    }
+
+    #[compio::test]
+    async fn test_forward_stops_when_receiver_dropped() {
+        let (nltx, nlrx) = unbounded();
+        let (tx, rx) = unbounded();
+        drop(rx);
+
+        let msg = RouteNetlinkMessage::NewAddress(v4_msg(2, Ipv4Addr::new(192, 168, 1, 2)));
+        nltx.unbounded_send((NetlinkMessage::from(msg), SocketAddr::new(0, 0))).unwrap();
+
+        // The subscription is still open, so only the closed receiver stops it.
+        let forward = forward_events(nlrx, links(&[("eth0", 2)]), tx);
+        compio::runtime::time::timeout(Duration::from_secs(5), forward).await
+            .expect("Forwarding should stop once the receiver is dropped");
+        assert!(nltx.is_closed(), "The subscription should be dropped");
+    }
+
+    #[test]
+    fn test_filter_msg_alias() {
+        let mut ifaces = links(&[("eth0", 2)]);
+        let ip = Ipv4Addr::new(192, 168, 1, 2);
+
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.index = 2;
+        addr.attributes = vec![
+            AddressAttribute::Label("eth0:1".to_string()),
+            AddressAttribute::Address(IpAddr::V4(ip)),
+        ];
+
+        let change = addr_change(filter_msg(&mut ifaces, RouteNetlinkMessage::NewAddress(addr), false))
+            .expect("Aliases should match their interface");
+        assert_eq!(change.iface, "eth0");
+        assert_eq!(change.addr.label.as_deref(), Some("eth0:1"));
+    }
+
+    #[test]
+    fn test_get_addr_metadata() {
+        let ip = Ipv4Addr::new(10, 0, 0, 2);
+        let mut addr = AddressMessage::default();
+        addr.header.family = AddressFamily::Inet;
+        addr.header.prefix_len = 24;
+        addr.header.flags = AddressHeaderFlags::Secondary;
+        addr.attributes = vec![
+            AddressAttribute::Label("eth0:1".to_string()),
+            AddressAttribute::Address(IpAddr::V4(ip)),
+        ];
+
+        let ifaddr = get_addr(&addr).expect("Should have an address");
+        assert_eq!(ifaddr.ip, IpAddr::V4(ip));
+        assert_eq!(ifaddr.prefix_len, 24);
+        assert_eq!(ifaddr.label.as_deref(), Some("eth0:1"));
+        assert!(ifaddr.is_secondary());
+
+        // The attribute flags take precedence over the header
+        addr.attributes.push(AddressAttribute::Flags(AddressFlags::Permanent));
+        let ifaddr = get_addr(&addr).expect("Should have an address");
+        assert!(!ifaddr.is_secondary());
+    }
 }
@@ -0,0 +1,88 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use sd_notify::NotifyState;
+use tracing::debug;
+
+// These are no-ops when not running under systemd, i.e. when NOTIFY_SOCKET
+// is not set.
+
+fn send(states: &[NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, states) {
+        debug!("Failed to notify systemd: {err}");
+    }
+}
+
+/// Tell systemd that startup has finished.
+pub fn ready(status: &str) {
+    send(&[NotifyState::Ready, NotifyState::Status(status)]);
+}
+
+/// Update the status line shown by `systemctl status`.
+pub fn status(status: &str) {
+    send(&[NotifyState::Status(status)]);
+}
+
+/// Tell systemd that we are shutting down.
+pub fn stopping() {
+    send(&[NotifyState::Stopping]);
+}
+
+/// Tell systemd that we are still alive.
+pub fn watchdog() {
+    send(&[NotifyState::Watchdog]);
+}
+
+/// How often to ping the watchdog, if it is enabled for this service; this
+/// is half the configured timeout, as recommended by sd_watchdog_enabled(3).
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec)
+        .then(|| Duration::from_micros(usec / 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn test_ready() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let sock = UnixDatagram::bind(&path).unwrap();
+
+        temp_env::with_var("NOTIFY_SOCKET", Some(&path), || ready("synced 192.0.2.1"));
+
+        let mut buf = [0; 128];
+        let len = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1\nSTATUS=synced 192.0.2.1\n");
+    }
+
+    #[test]
+    fn test_watchdog_interval() {
+        let pid = std::process::id().to_string();
+        temp_env::with_vars([("WATCHDOG_USEC", Some("2000000")), ("WATCHDOG_PID", Some(pid.as_str()))], || {
+            assert_eq!(watchdog_interval(), Some(Duration::from_secs(1)));
+        });
+        temp_env::with_vars_unset(["WATCHDOG_USEC", "WATCHDOG_PID"], || {
+            assert_eq!(watchdog_interval(), None);
+        });
+    }
+}
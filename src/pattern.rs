@@ -0,0 +1,174 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use anyhow::{Context, Result};
+use regex_automata::meta::Regex;
+
+/// A pattern matching interface names, for interfaces whose names aren't
+/// stable; e.g. `ppp0` vs `ppp1` across reconnects.
+///
+/// Patterns are either globs such as "ppp*" or "wg-site-[0-9]", which must
+/// match the whole name, or regular expressions between slashes such as
+/// "/^wg-site-\d+$/", which match anywhere unless anchored. Interface names
+/// can't contain a slash, so these can't be confused with a literal name.
+#[derive(Clone, Debug)]
+pub struct IfacePattern(Regex);
+
+impl IfacePattern {
+    /// Parse `iface` as a pattern, returning `None` if it is a plain
+    /// interface name.
+    pub fn parse(iface: &str) -> Result<Option<Self>> {
+        let regex = if let Some(re) = iface.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            re.to_string()
+        } else if iface.contains(['*', '?', '[']) {
+            glob_to_regex(iface)
+        } else {
+            return Ok(None);
+        };
+
+        let regex = Regex::new(&regex)
+            .with_context(|| format!("Invalid interface pattern `{iface}`"))?;
+        Ok(Some(IfacePattern(regex)))
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+}
+
+fn push_literal(regex: &mut String, c: char) {
+    // Any ASCII punctuation may be escaped.
+    if c.is_ascii_punctuation() && c != '<' && c != '>' {
+        regex.push('\\');
+    }
+    regex.push(c);
+}
+
+/// Translate a shell-style glob into an anchored regular expression.
+fn glob_to_regex(glob: &str) -> String {
+    let chars = glob.chars().collect::<Vec<char>>();
+    let mut regex = String::from("^");
+
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let negated = chars.get(i + 1) == Some(&'!');
+                let body = i + 1 + usize::from(negated);
+                // A `]` at the start of the class is part of it.
+                let close = chars.iter()
+                    .skip(body + 1)
+                    .position(|c| *c == ']')
+                    .map(|pos| body + 1 + pos);
+                let Some(close) = close else {
+                    // Not a class; match the bracket literally.
+                    push_literal(&mut regex, '[');
+                    i += 1;
+                    continue;
+                };
+
+                regex.push('[');
+                if negated {
+                    regex.push('^');
+                }
+                for &c in &chars[body..close] {
+                    if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+                i = close;
+            }
+            c => push_literal(&mut regex, c),
+        }
+        i += 1;
+    }
+
+    regex.push('$');
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(iface: &str) -> IfacePattern {
+        IfacePattern::parse(iface)
+            .expect("Should be valid")
+            .expect("Should be a pattern")
+    }
+
+    #[test]
+    fn test_plain_names() -> Result<()> {
+        assert!(IfacePattern::parse("eth0")?.is_none());
+        assert!(IfacePattern::parse("wg-site.1")?.is_none());
+        assert!(IfacePattern::parse("ppp*")?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob() {
+        let ppp = pattern("ppp*");
+        assert!(ppp.is_match("ppp0"));
+        assert!(ppp.is_match("ppp12"));
+        assert!(!ppp.is_match("eth0"));
+        assert!(!ppp.is_match("xppp0"));
+
+        let wg = pattern("wg-site.?");
+        assert!(wg.is_match("wg-site.1"));
+        assert!(!wg.is_match("wg-sitex1"));
+        assert!(!wg.is_match("wg-site.12"));
+    }
+
+    #[test]
+    fn test_glob_class() {
+        let wan = pattern("wan[0-2]");
+        assert!(wan.is_match("wan1"));
+        assert!(!wan.is_match("wan3"));
+
+        let not = pattern("wan[!0]");
+        assert!(not.is_match("wan1"));
+        assert!(!not.is_match("wan0"));
+
+        let bracket = pattern("tun[]x]");
+        assert!(bracket.is_match("tun]"));
+        assert!(bracket.is_match("tunx"));
+
+        let unclosed = pattern("tun[0");
+        assert!(unclosed.is_match("tun[0"));
+        assert!(!unclosed.is_match("tun0"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let wg = pattern(r"/^wg-site-\d+$/");
+        assert!(wg.is_match("wg-site-12"));
+        assert!(!wg.is_match("wg-site-"));
+
+        let unanchored = pattern("/ppp/");
+        assert!(unanchored.is_match("ppp0"));
+        assert!(unanchored.is_match("vppp"));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(IfacePattern::parse("/ppp(/").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    env,
+    fmt::{self, Debug, Display, Formatter},
+    fs::read_to_string,
+    ops::Deref,
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// A value containing secrets, which is redacted when formatted.
+#[derive(Clone, Deserialize, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl<T> Display for Secret<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// Resolve a secret reference to its value.
+///
+/// References take the form `file:/path`, `credential:name` (a systemd
+/// credential from `$CREDENTIALS_DIRECTORY`, see `LoadCredential=`) or
+/// `env:NAME`; any other value is returned as-is. Trailing newlines are
+/// stripped from files and credentials.
+pub fn resolve(value: &str) -> Result<String> {
+    if let Some(path) = value.strip_prefix("file:") {
+        read_secret(Path::new(path))
+
+    } else if let Some(name) = value.strip_prefix("credential:") {
+        if name.is_empty() || name.contains('/') {
+            bail!("Invalid credential name `{name}`");
+        }
+        let dir = env::var_os("CREDENTIALS_DIRECTORY")
+            .with_context(|| format!("Credential `{name}` requested but $CREDENTIALS_DIRECTORY is not set; \
+                                      use LoadCredential= in the systemd unit"))?;
+        read_secret(&Path::new(&dir).join(name))
+            .with_context(|| format!("Failed to load credential `{name}`"))
+
+    } else if let Some(name) = value.strip_prefix("env:") {
+        env::var(name)
+            .with_context(|| format!("Environment variable `{name}` is not set"))
+
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+fn read_secret(path: &Path) -> Result<String> {
+    let secret = read_to_string(path)
+        .with_context(|| format!("Failed to read secret from {}", path.display()))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Resolve all the string values in `value` except `name`, which is the
+/// provider tag.
+pub fn resolve_all(value: &mut Value) -> Result<()> {
+    resolve_in(value, "")
+}
+
+fn resolve_in(value: &mut Value, path: &str) -> Result<()> {
+    match value {
+        Value::String(s) if path != "name" => {
+            *s = resolve(s).with_context(|| format!("Invalid secret `{path}`"))?;
+        }
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                let path = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+                resolve_in(v, &path)?;
+            }
+        }
+        Value::Array(values) => {
+            for (i, v) in values.iter_mut().enumerate() {
+                resolve_in(v, &format!("{path}[{i}]"))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redacted() {
+        let secret = Secret::new("a_key".to_string());
+        assert_eq!(format!("{secret:?}"), "***");
+        assert_eq!(format!("{secret}"), "***");
+        assert_eq!(format!("{secret:#?}"), "***");
+        assert_eq!(*secret, "a_key");
+    }
+
+    #[test]
+    fn test_plain() -> Result<()> {
+        assert_eq!(resolve("a_key")?, "a_key");
+        Ok(())
+    }
+
+    #[test]
+    fn test_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("key");
+        fs::write(&file, "a_key\n")?;
+
+        assert_eq!(resolve(&format!("file:{}", file.display()))?, "a_key");
+        assert!(resolve(&format!("file:{}", dir.path().join("missing").display())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_credential() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("porkbun-key"), "a_key")?;
+
+        temp_env::with_var("CREDENTIALS_DIRECTORY", Some(dir.path()), || {
+            assert_eq!(resolve("credential:porkbun-key").unwrap(), "a_key");
+            assert!(resolve("credential:missing").is_err());
+            assert!(resolve("credential:../porkbun-key").is_err());
+        });
+        temp_env::with_var_unset("CREDENTIALS_DIRECTORY", || {
+            let err = resolve("credential:porkbun-key").unwrap_err();
+            assert!(err.to_string().contains("CREDENTIALS_DIRECTORY"));
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_env() {
+        temp_env::with_var("NETLINK_DDNS_TEST_KEY", Some("a_key"), || {
+            assert_eq!(resolve("env:NETLINK_DDNS_TEST_KEY").unwrap(), "a_key");
+        });
+        temp_env::with_var_unset("NETLINK_DDNS_TEST_KEY", || {
+            let err = resolve("env:NETLINK_DDNS_TEST_KEY").unwrap_err();
+            assert!(err.to_string().contains("NETLINK_DDNS_TEST_KEY"));
+        });
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let mut value = json!({ "name": "env:NOT_RESOLVED", "key": "env:NETLINK_DDNS_TEST_SECRET" });
+        temp_env::with_var("NETLINK_DDNS_TEST_SECRET", Some("a_secret"), || {
+            resolve_all(&mut value).unwrap();
+        });
+        assert_eq!(value, json!({ "name": "env:NOT_RESOLVED", "key": "a_secret" }));
+
+        let mut value = json!({ "name": "porkbun", "secret": "env:NETLINK_DDNS_TEST_MISSING" });
+        let err = resolve_all(&mut value).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid secret `secret`");
+    }
+}
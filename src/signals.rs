@@ -0,0 +1,84 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::thread;
+
+use anyhow::Result;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1},
+    iterator::Signals,
+};
+use tracing::debug;
+
+/// The actions requested by signals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGTERM, SIGQUIT or SIGINT
+    Shutdown,
+    /// SIGUSR1
+    Resync,
+    /// SIGHUP
+    Reload,
+}
+
+fn to_signal(signum: i32) -> Option<Signal> {
+    match signum {
+        SIGTERM | SIGQUIT | SIGINT => Some(Signal::Shutdown),
+        SIGUSR1 => Some(Signal::Resync),
+        SIGHUP => Some(Signal::Reload),
+        _ => None,
+    }
+}
+
+/// Install the signal handlers, returning a stream of the received signals.
+///
+/// The handlers only forward the signal to a dedicated thread, which passes
+/// them on to the runtime.
+pub fn signal_stream() -> Result<UnboundedReceiver<Signal>> {
+    let mut signals = Signals::new([SIGTERM, SIGQUIT, SIGINT, SIGUSR1, SIGHUP])?;
+    let (tx, rx) = unbounded();
+
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || {
+            for signum in signals.forever() {
+                debug!("Received signal {signum}");
+                if let Some(signal) = to_signal(signum)
+                    && tx.unbounded_send(signal).is_err()
+                {
+                    break;
+                }
+            }
+        })?;
+
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_signal() {
+        assert_eq!(to_signal(SIGTERM), Some(Signal::Shutdown));
+        assert_eq!(to_signal(SIGQUIT), Some(Signal::Shutdown));
+        assert_eq!(to_signal(SIGINT), Some(Signal::Shutdown));
+        assert_eq!(to_signal(SIGUSR1), Some(Signal::Resync));
+        assert_eq!(to_signal(SIGHUP), Some(Signal::Reload));
+        assert_eq!(to_signal(signal_hook::consts::SIGCHLD), None);
+    }
+}
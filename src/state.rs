@@ -0,0 +1,182 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Write,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use rtnetlink::packet_route::AddressFamily;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{config::HostConfig, ddns::rtype};
+
+/// The last known state of a published record.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RecordState {
+    /// The published address; `None` if the record was deleted
+    pub address: Option<IpAddr>,
+    /// When the record was last changed or confirmed, in seconds since the epoch
+    pub timestamp: u64,
+    /// The outcome of the provider call; e.g. "created" or "updated"
+    pub response: String,
+}
+
+impl RecordState {
+    pub fn new(address: Option<IpAddr>, response: impl Into<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        RecordState {
+            address,
+            timestamp,
+            response: response.into(),
+        }
+    }
+}
+
+/// The key identifying a record in the state file; e.g. "www.example.com/AAAA".
+pub fn record_key(host: &HostConfig, family: AddressFamily) -> String {
+    format!("{}/{}", host.fqdn(), rtype(family))
+}
+
+/// Persistent record state, used to avoid redundant provider calls across
+/// restarts.
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    records: BTreeMap<String, RecordState>,
+}
+
+impl StateFile {
+    /// Load the state from `path`.
+    ///
+    /// A missing file results in an empty state. As the state is only a
+    /// cache, an unreadable or corrupt file is logged and ignored.
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let records = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .unwrap_or_else(|err| {
+                    warn!("Ignoring corrupt state file {}: {err}", path.display());
+                    BTreeMap::new()
+                }),
+            Err(err) => {
+                debug!("No state loaded from {}: {err}", path.display());
+                BTreeMap::new()
+            }
+        };
+        StateFile { path, records }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&RecordState> {
+        self.records.get(key)
+    }
+
+    /// Update a record and write the state to disk.
+    pub fn set(&mut self, key: String, state: RecordState) -> Result<()> {
+        self.records.insert(key, state);
+        self.save()
+    }
+
+    /// Atomically replace the state file; the new state is written to a
+    /// temporary file in the same directory and renamed over the old one.
+    pub fn save(&self) -> Result<()> {
+        let dir = self.path.parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create state directory {}", dir.display()))?;
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let json = serde_json::to_string_pretty(&self.records)?;
+        let mut file = File::create(&tmp)
+            .with_context(|| format!("Failed to write state file {}", tmp.display()))?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to replace state file {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AddrSelect, AddrSource, IpVersion, OnDelete};
+
+    #[test]
+    fn test_record_key() {
+        let host = HostConfig {
+            name: "www".to_string(),
+            domain: "example.com".to_string(),
+            iface: "eth0".to_string(),
+            ip_version: IpVersion::Both,
+            select: AddrSelect::Primary,
+            source: AddrSource::Netlink,
+            on_delete: OnDelete::Ignore,
+        };
+        assert_eq!(record_key(&host, AddressFamily::Inet), "www.example.com/A");
+        assert_eq!(record_key(&host, AddressFamily::Inet6), "www.example.com/AAAA");
+    }
+
+    #[test]
+    fn test_missing_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let state = StateFile::load(dir.path().join("state.json"));
+        assert!(state.get("test.example.com/A").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        let rs = RecordState::new(Some("192.0.2.1".parse()?), "created");
+
+        let mut state = StateFile::load(&path);
+        state.set("test.example.com/A".to_string(), rs.clone())?;
+        state.set("test.example.com/AAAA".to_string(), RecordState::new(None, "deleted"))?;
+
+        let reloaded = StateFile::load(&path);
+        assert_eq!(reloaded.get("test.example.com/A"), Some(&rs));
+        assert_eq!(reloaded.get("test.example.com/AAAA").map(|r| r.address), Some(None));
+        assert!(!path.with_extension("json.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        fs::write(&path, "{ not json")?;
+
+        let state = StateFile::load(&path);
+        assert!(state.get("test.example.com/A").is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,1260 @@
+// netlink-ddns: A DDNS client on netlink
+// Copyright (C) 2025 tarkasteve@gmail.com
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    cell::RefCell,
+    fmt::{self, Display, Formatter},
+    future::{poll_fn, Future},
+    net::IpAddr,
+    pin::Pin,
+    rc::Rc,
+    task::Poll,
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::{
+    channel::{
+        mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    future::{join_all, select, Either},
+    FutureExt, StreamExt,
+};
+use rtnetlink::packet_route::AddressFamily;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{AddrSelect, AddrSource, Config, Filter, HostConfig, NonGlobal, OnDelete, Retry},
+    ddns::{get_dns_provider, DnsClient},
+    discovery::discover,
+    filter::classify,
+    metrics,
+    netlink::{self, IfAddr, LinkChange, LinkState, AUTO_IFACE},
+    notify,
+    secrets::Secret,
+    state::{record_key, RecordState, StateFile},
+};
+
+/// What a record should be changed to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Target {
+    /// A known local address
+    Addr(IpAddr),
+    /// The address reported by the host's discovery source
+    Discover,
+    /// A configured fallback address; this bypasses the address filter
+    Fallback(IpAddr),
+    /// Delete the record
+    Remove,
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Addr(ip) => write!(f, "{ip}"),
+            Target::Discover => write!(f, "discovered address"),
+            Target::Fallback(ip) => write!(f, "fallback {ip}"),
+            Target::Remove => write!(f, "removed"),
+        }
+    }
+}
+
+/// The result of applying a target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Created,
+    Updated,
+    Unchanged,
+    Deleted,
+    /// Nothing was published, e.g. because the address was filtered
+    Skipped,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::Unchanged => "unchanged",
+            Outcome::Deleted => "deleted",
+            Outcome::Skipped => "skipped",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// The state of a record with no update in progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Nothing has been applied yet
+    Idle,
+    /// The last update succeeded
+    Synced(Outcome),
+    /// The last update failed and is waiting to be retried
+    Failed,
+}
+
+/// Requests sent to a record worker.
+#[derive(Debug)]
+enum Command {
+    /// Make the record point at this target
+    Publish(Target),
+    /// This address was removed from the interface
+    Deleted(IpAddr),
+    /// Reply once no update is in progress
+    Settle(oneshot::Sender<Status>),
+    /// Re-read the upstream record and re-apply the last target
+    Resync,
+}
+
+/// Things that wake up an idle record worker.
+enum Event {
+    Command(Command),
+    Closed,
+    Retry,
+    GraceExpired,
+    Reconcile,
+}
+
+type Timer = Pin<Box<dyn Future<Output = ()>>>;
+
+fn timer(delay: Duration) -> Timer {
+    Box::pin(compio::runtime::time::sleep(delay))
+}
+
+/// Calculate the delay before retry number `attempt` (starting at 0).
+///
+/// The delay doubles on each attempt up to `max_delay`, and a random jitter
+/// of up to half the delay is subtracted so that several failing records
+/// don't retry in lock-step.
+fn backoff(retry: &Retry, attempt: u32) -> Duration {
+    let exp = retry.initial_delay
+        .saturating_mul(2u64.saturating_pow(attempt))
+        .min(retry.max_delay);
+    let millis = exp.saturating_mul(1000);
+    let jitter = fastrand::u64(0..=millis / 2);
+    Duration::from_millis(millis - jitter)
+}
+
+/// A single published address record (A or AAAA) for a host.
+struct Record {
+    host: HostConfig,
+    family: AddressFamily,
+    client: DnsClient,
+    retry: Retry,
+    filter: Filter,
+    /// The last address known to be published upstream.
+    published: Option<IpAddr>,
+    /// The last local address applied; this may differ from `published`
+    /// when a fallback is used.
+    local: Option<IpAddr>,
+    /// The target waiting to be applied, if any.
+    pending: Option<Target>,
+    /// Set while waiting to retry a failed update.
+    retry_timer: Option<Timer>,
+    /// Set while waiting to act on a deleted address.
+    grace_timer: Option<Timer>,
+    /// The last target successfully applied.
+    last_target: Option<Target>,
+    reconcile_interval: Option<Duration>,
+    /// Set while waiting for the next reconciliation.
+    reconcile_timer: Option<Timer>,
+    /// The shared state file, if enabled.
+    state: Option<Rc<RefCell<StateFile>>>,
+    /// Nothing is really published in a dry run, so nothing is saved.
+    dry_run: bool,
+    status: Status,
+    /// Waiting for the record to settle.
+    waiters: Vec<oneshot::Sender<Status>>,
+}
+
+impl Record {
+    fn new(host: HostConfig, family: AddressFamily, client: DnsClient, config: &Config,
+           state: Option<Rc<RefCell<StateFile>>>) -> Self
+    {
+        let published = state.as_ref()
+            .and_then(|s| s.borrow().get(&record_key(&host, family)).and_then(|rs| rs.address));
+        if let Some(ip) = published {
+            debug!("Loaded last published address {ip} for {} from state", host.name);
+        }
+
+        Record {
+            host,
+            family,
+            client,
+            retry: config.retry.clone(),
+            filter: config.ddns.filter.clone(),
+            published,
+            local: None,
+            pending: None,
+            retry_timer: None,
+            grace_timer: None,
+            last_target: None,
+            reconcile_interval: config.reconcile_interval.map(Duration::from_secs),
+            reconcile_timer: None,
+            state,
+            dry_run: config.dry_run,
+            status: Status::Idle,
+            waiters: Vec::new(),
+        }
+    }
+
+    fn save_state(&self, outcome: Outcome) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        if self.dry_run {
+            return;
+        }
+        let rs = RecordState::new(self.published, outcome.to_string());
+        if let Err(err) = state.borrow_mut().set(record_key(&self.host, self.family), rs) {
+            warn!("Failed to save state for {}: {err:#}", self.host.name);
+        }
+    }
+
+    /// Apply the address filter to `ip`, returning the address to publish,
+    /// if any.
+    async fn check(&self, ip: IpAddr) -> Result<Option<IpAddr>> {
+        if self.filter.is_publishable(&ip) {
+            return Ok(Some(ip));
+        }
+
+        let class = classify(&ip);
+        match &self.filter.non_global {
+            NonGlobal::Skip => {
+                warn!("Not publishing {class} address {ip} for {}", self.host.name);
+                Ok(None)
+            }
+            NonGlobal::Warn => {
+                warn!("Publishing {class} address {ip} for {}", self.host.name);
+                Ok(Some(ip))
+            }
+            NonGlobal::Fallback(source) => {
+                info!("Address {ip} for {} is {class}; discovering public address", self.host.name);
+                let public = discover(source, self.family).await?;
+                if self.filter.is_publishable(&public) {
+                    Ok(Some(public))
+                } else {
+                    warn!("Discovered address {public} for {} is {}; not publishing",
+                          self.host.name, classify(&public));
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn remove(&mut self) -> Result<Outcome> {
+        info!("Deleting {:?} DNS record for {}", self.family, self.host.name);
+        self.client.delete_addr_record(&self.host.name, self.family).await?;
+        self.published = None;
+        Ok(Outcome::Deleted)
+    }
+
+    async fn publish(&mut self, target: Target) -> Result<Outcome> {
+        let local = match target {
+            Target::Addr(ip) => self.check(ip).await?,
+            Target::Discover => {
+                info!("Discovering public {:?} address for {}", self.family, self.host.name);
+                let ip = discover(&self.host.source, self.family).await?;
+                self.check(ip).await?
+            }
+            Target::Fallback(ip) => Some(ip),
+            Target::Remove => return self.remove().await,
+        };
+        let Some(local) = local else {
+            return Ok(Outcome::Skipped);
+        };
+
+        let upstream = match self.published {
+            Some(ip) => Some(ip),
+            None => {
+                info!("Fetching published {:?} DNS record for {}", self.family, self.host.name);
+                self.client.get_addr_record(&self.host.name, self.family).await?
+            }
+        };
+
+        let outcome = if upstream.is_none()  {
+            info!("No existing DNS record for {}; creating", self.host.name);
+            self.client.create_addr_record(&self.host.name, &local).await?;
+            Outcome::Created
+
+        } else if Some(local) != upstream {
+            info!("DNS record for {} out of date; updating", self.host.name);
+            self.client.update_addr_record(&self.host.name, &local).await?;
+            Outcome::Updated
+
+        } else {
+            info!("DNS record for {} is up-to-date: {local}", self.host.name);
+            Outcome::Unchanged
+        };
+
+        self.published = Some(local);
+        Ok(outcome)
+    }
+
+    /// The target to apply once the deletion grace period expires.
+    fn deletion_target(&self) -> Option<Target> {
+        match &self.host.on_delete {
+            OnDelete::Ignore => None,
+            OnDelete::Delete { .. } => Some(Target::Remove),
+            OnDelete::Fallback { addresses, .. } => {
+                let fallback = addresses.iter()
+                    .find(|a| (self.family == AddressFamily::Inet6) == a.is_ipv6())
+                    .copied()
+                    .map(Target::Fallback);
+                if fallback.is_none() {
+                    warn!("No {:?} fallback address configured for {}", self.family, self.host.name);
+                }
+                fallback
+            }
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Publish(target) => {
+                if let Target::Addr(ip) = target && self.local.is_some_and(|l| l != ip) {
+                    info!("Local address for {} changed to {ip}", self.host.name);
+                }
+                if self.retry_timer.is_some() {
+                    info!("Superseding pending update of {} with {target}", self.host.name);
+                }
+                if self.grace_timer.take().is_some() {
+                    info!("Replacement address for {}; cancelling deletion action", self.host.name);
+                }
+                self.pending = Some(target);
+            }
+            Command::Deleted(ip) => {
+                let current = self.local == Some(ip) || self.pending == Some(Target::Addr(ip));
+                if !current {
+                    debug!("Deleted address {ip} is not published for {}", self.host.name);
+                    return;
+                }
+                if self.pending == Some(Target::Addr(ip)) {
+                    self.pending = None;
+                    self.retry_timer = None;
+                }
+                match self.host.on_delete.grace() {
+                    Some(grace) => {
+                        info!("Address {ip} for {} was deleted; acting in {grace:?} unless replaced",
+                              self.host.name);
+                        self.grace_timer = Some(timer(grace));
+                    }
+                    None => info!("Address {ip} for {} was deleted; leaving record", self.host.name),
+                }
+            }
+            Command::Settle(tx) => self.waiters.push(tx),
+            Command::Resync => {
+                // Forget the published address so it is re-read from the provider.
+                self.published = None;
+                self.retry_timer = None;
+                if self.pending.is_none() {
+                    self.pending = self.last_target;
+                }
+                if self.pending.is_some() {
+                    info!("Re-syncing {:?} DNS record for {}", self.family, self.host.name);
+                }
+            }
+        }
+    }
+
+    /// Record the successful application of `target`.
+    fn applied(&mut self, target: Target, outcome: Outcome) {
+        if outcome == Outcome::Skipped {
+            // Nothing was published, so there is nothing to reconcile against.
+            self.last_target = None;
+            self.reconcile_timer = None;
+        } else {
+            self.save_state(outcome);
+            metrics::update_succeeded(&self.host, self.family, self.published);
+            let addr = self.published.map_or("none".to_string(), |ip| ip.to_string());
+            notify::status(&format!("synced {} {addr}", self.host.fqdn()));
+            self.last_target = Some(target);
+            self.schedule_reconcile();
+        }
+        self.status = Status::Synced(outcome);
+        self.local = match target {
+            Target::Addr(ip) => Some(ip),
+            _ => None,
+        };
+        self.pending = None;
+    }
+
+    fn schedule_reconcile(&mut self) {
+        self.reconcile_timer = self.reconcile_interval.map(timer);
+    }
+
+    /// Re-read the upstream record and re-apply the last target if it has
+    /// been changed behind our back.
+    async fn reconcile(&mut self) {
+        self.schedule_reconcile();
+        let Some(target) = self.last_target else {
+            return;
+        };
+
+        debug!("Reconciling {:?} DNS record for {}", self.family, self.host.name);
+        match self.client.get_addr_record(&self.host.name, self.family).await {
+            Ok(upstream) => self.compare_upstream(target, upstream),
+            Err(err) => {
+                warn!("Failed to fetch {:?} DNS record for {}: {err:#}", self.family, self.host.name);
+            }
+        }
+    }
+
+    /// Queue `target` again if the upstream record differs from the one we
+    /// published.
+    fn compare_upstream(&mut self, target: Target, upstream: Option<IpAddr>) {
+        if upstream != self.published {
+            let show = |ip: Option<IpAddr>| ip.map_or("none".to_string(), |ip| ip.to_string());
+            warn!("Drift detected for {}: upstream is {}, expected {}; correcting",
+                  self.host.name, show(upstream), show(self.published));
+            metrics::drift_detected(&self.host, self.family);
+            self.published = upstream;
+            self.pending = Some(target);
+        } else if target == Target::Discover {
+            // The public address can change without any local event.
+            self.pending = Some(target);
+        } else {
+            debug!("DNS record for {} matches upstream", self.host.name);
+        }
+    }
+
+    /// Wait for a command or for one of the timers to fire.
+    async fn next_event(&mut self, rx: &mut UnboundedReceiver<Command>) -> Event {
+        poll_fn(|cx| {
+            if let Some(t) = self.retry_timer.as_mut() && t.poll_unpin(cx).is_ready() {
+                self.retry_timer = None;
+                return Poll::Ready(Event::Retry);
+            }
+            if let Some(t) = self.grace_timer.as_mut() && t.poll_unpin(cx).is_ready() {
+                self.grace_timer = None;
+                return Poll::Ready(Event::GraceExpired);
+            }
+            if let Some(t) = self.reconcile_timer.as_mut() && t.poll_unpin(cx).is_ready() {
+                self.reconcile_timer = None;
+                return Poll::Ready(Event::Reconcile);
+            }
+            rx.poll_next_unpin(cx)
+                .map(|cmd| cmd.map_or(Event::Closed, Event::Command))
+        }).await
+    }
+
+    /// Process commands until the sending side is closed.
+    ///
+    /// Failed updates are retried with backoff. Targets received while
+    /// waiting to retry replace the pending one, so only the most recent
+    /// address is ever published.
+    async fn run(mut self, mut rx: UnboundedReceiver<Command>) {
+        let mut attempt = 0;
+        // The target of the last failed update, if it is being retried.
+        let mut failed = None;
+
+        loop {
+            if self.pending.is_none() || self.retry_timer.is_some() {
+                for tx in self.waiters.drain(..) {
+                    let _ = tx.send(self.status);
+                }
+            }
+
+            if let Some(target) = self.pending && self.retry_timer.is_none() {
+                // Skip to the most recent request if several are queued.
+                let mut superseded = false;
+                while let Ok(cmd) = rx.try_recv() {
+                    self.handle_command(cmd);
+                    superseded = true;
+                }
+                if superseded {
+                    continue;
+                }
+
+                // A new target starts with a fresh backoff.
+                if failed != Some(target) {
+                    attempt = 0;
+                }
+
+                metrics::update_attempted(&self.host, self.family);
+                match self.publish(target).await {
+                    Ok(outcome) => {
+                        self.applied(target, outcome);
+                        attempt = 0;
+                        failed = None;
+                    }
+                    Err(err) => {
+                        // The upstream state is unknown after a failure.
+                        self.published = None;
+                        metrics::update_failed(&self.host, self.family);
+                        self.status = Status::Failed;
+
+                        let delay = backoff(&self.retry, attempt);
+                        attempt = attempt.saturating_add(1);
+                        failed = Some(target);
+                        warn!("Failed to update {} to {target}: {err:#}; retrying in {delay:?}",
+                              self.host.name);
+                        self.retry_timer = Some(timer(delay));
+                    }
+                }
+                continue;
+            }
+
+            match self.next_event(&mut rx).await {
+                Event::Command(cmd) => self.handle_command(cmd),
+                Event::Closed => break,
+                Event::Retry => {}
+                Event::GraceExpired => {
+                    self.pending = self.deletion_target();
+                    self.retry_timer = None;
+                    attempt = 0;
+                }
+                Event::Reconcile => self.reconcile().await,
+            }
+        }
+    }
+}
+
+/// A handle to a record worker.
+struct RecordHandle {
+    host: HostConfig,
+    family: AddressFamily,
+    tx: UnboundedSender<Command>,
+    /// The position of this record among those of a host publishing all its
+    /// addresses; always 0 otherwise.
+    slot: usize,
+    /// The address assigned to this record, for hosts publishing all their
+    /// addresses.
+    addr: Option<IpAddr>,
+}
+
+impl RecordHandle {
+    /// Whether this is one of the `family` records of `host`.
+    fn is_for(&self, host: &HostConfig, family: AddressFamily) -> bool {
+        self.family == family && self.host == host.slot(self.slot)
+    }
+}
+
+/// The settings shared by every record; all records are rebuilt if any of
+/// these change on reload.
+#[derive(Debug, PartialEq, Eq)]
+struct Shared {
+    /// `Provider` doesn't implement `PartialEq`, so compare its debug form.
+    /// This includes the credentials, so keep it redacted.
+    provider: Secret<String>,
+    dry_run: bool,
+    retry: Retry,
+    filter: Filter,
+    reconcile_interval: Option<u64>,
+    state_file: Option<String>,
+}
+
+impl Shared {
+    fn new(config: &Config) -> Self {
+        Shared {
+            provider: Secret::new(format!("{:?}", *config.ddns.provider)),
+            dry_run: config.dry_run,
+            retry: config.retry.clone(),
+            filter: config.ddns.filter.clone(),
+            reconcile_interval: config.reconcile_interval,
+            state_file: config.state_file.clone(),
+        }
+    }
+}
+
+fn spawn_record(host: &HostConfig, slot: usize, family: AddressFamily, client: DnsClient, config: &Config,
+                state: Option<Rc<RefCell<StateFile>>>) -> RecordHandle
+{
+    let host = host.slot(slot);
+    let record = Record::new(host.clone(), family, client, config, state);
+    let (tx, rx) = unbounded();
+    compio::runtime::spawn(record.run(rx))
+        .detach();
+    RecordHandle { host, family, tx, slot, addr: None }
+}
+
+fn load_state(config: &Config) -> Option<Rc<RefCell<StateFile>>> {
+    config.state_file.as_ref()
+        .map(|path| Rc::new(RefCell::new(StateFile::load(path))))
+}
+
+/// Keeps the DNS records of all configured hosts in sync with the local
+/// addresses.
+///
+/// Each record is managed by its own task, so a failing or slow provider
+/// call for one host never blocks the others or the monitoring stream.
+pub struct Updater {
+    /// Hosts publishing all their addresses gain records as addresses
+    /// appear, so these can change outside of `reload()`.
+    records: RefCell<Vec<RecordHandle>>,
+    state: Option<Rc<RefCell<StateFile>>>,
+    shared: Shared,
+    /// Used to start the additional records of hosts publishing all their
+    /// addresses.
+    config: Config,
+}
+
+impl Updater {
+    /// Create the updater and spawn the record workers; must be called
+    /// from within the runtime.
+    pub fn new(config: &Config) -> Result<Self> {
+        let state = load_state(config);
+
+        let mut records = Vec::new();
+        for host in config.hosts() {
+            for family in host.ip_version.families() {
+                let client = get_dns_provider(config, &host.domain)?;
+                records.push(spawn_record(&host, 0, family, client, config, state.clone()));
+            }
+        }
+        Ok(Updater {
+            records: RefCell::new(records),
+            state,
+            shared: Shared::new(config),
+            config: config.clone(),
+        })
+    }
+
+    /// Apply a new configuration.
+    ///
+    /// Records whose settings are unchanged keep running; new or changed
+    /// records get a fresh worker, and the workers of removed records are
+    /// stopped once any in-flight update finishes. If a new provider client
+    /// can't be created the running records are left untouched.
+    pub fn reload(&mut self, config: &Config) -> Result<()> {
+        let shared = Shared::new(config);
+        let rebuild = shared != self.shared;
+        if rebuild {
+            info!("DNS provider settings changed; rebuilding all records");
+        }
+
+        // Create all the clients before touching the running records.
+        let mut kept = Vec::new();
+        let mut added = Vec::new();
+        for host in config.hosts() {
+            for family in host.ip_version.families() {
+                let existing = self.records.get_mut().iter()
+                    .enumerate()
+                    .filter(|(_, r)| !rebuild && r.is_for(&host, family))
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                if existing.is_empty() {
+                    added.push((host.clone(), family, get_dns_provider(config, &host.domain)?));
+                }
+                kept.extend(existing);
+            }
+        }
+
+        if shared.state_file != self.shared.state_file {
+            self.state = load_state(config);
+        }
+
+        let records = self.records.get_mut();
+        let mut old = std::mem::take(records).into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        for i in kept {
+            records.extend(old[i].take());
+        }
+        // Dropping the handles of the remaining records stops their workers.
+        for removed in old.into_iter().flatten() {
+            info!("Stopping {:?} record for {}", removed.family, removed.host.name);
+        }
+        for (host, family, client) in added {
+            info!("Starting {family:?} record for {}", host.name);
+            records.push(spawn_record(&host, 0, family, client, config, self.state.clone()));
+        }
+
+        self.shared = shared;
+        self.config = config.clone();
+        Ok(())
+    }
+
+    /// Publish the addresses of `iface` to every record using that interface.
+    ///
+    /// Each record picks the first address of its family that satisfies its
+    /// host's selection policy; records without a match are left alone.
+    /// Records using an external discovery source instead treat any address
+    /// change on the interface as a trigger to re-check their public address.
+    /// Hosts publishing all their addresses get a record for each; see
+    /// `publish_slot()`.
+    pub fn publish(&self, iface: &str, addrs: &[IfAddr]) {
+        let mut all = Vec::new();
+        for record in self.records.borrow().iter()
+            .filter(|r| r.host.iface == iface)
+        {
+            if record.host.select == AddrSelect::All {
+                if record.slot == 0 {
+                    all.push((record.host.clone(), record.family));
+                }
+                continue;
+            }
+
+            let mut family_addrs = addrs.iter()
+                .filter(|a| (record.family == AddressFamily::Inet6) == a.ip.is_ipv6());
+
+            let target = if record.host.source != AddrSource::Netlink {
+                if family_addrs.next().is_none() {
+                    continue;
+                }
+                Target::Discover
+            } else if let Some(addr) = record.host.select.select(family_addrs) {
+                Target::Addr(addr.ip)
+            } else {
+                debug!("No address on {iface} matches the policy for {}", record.host.name);
+                continue;
+            };
+
+            if let Err(err) = record.tx.unbounded_send(Command::Publish(target)) {
+                error!("Updater for {} has stopped: {err}", record.host.name);
+            }
+        }
+
+        for (host, family) in all {
+            for addr in addrs.iter()
+                .filter(|a| (family == AddressFamily::Inet6) == a.ip.is_ipv6())
+            {
+                self.publish_slot(&host, family, addr.ip);
+            }
+        }
+    }
+
+    /// Publish `ip` to the record of `host` it was assigned to, or else to
+    /// the first free record, starting a new one if there are none.
+    ///
+    /// Records are freed when their address is removed, so a replacement
+    /// address takes over the record and cancels any `on_delete` action.
+    fn publish_slot(&self, host: &HostConfig, family: AddressFamily, ip: IpAddr) {
+        let mut records = self.records.borrow_mut();
+        let slots = records.iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_for(host, family))
+            .map(|(i, r)| (i, r.slot, r.addr))
+            .collect::<Vec<_>>();
+        let assigned = slots.iter()
+            .find(|(_, _, addr)| *addr == Some(ip))
+            .or_else(|| slots.iter()
+                .filter(|(_, _, addr)| addr.is_none())
+                .min_by_key(|(_, slot, _)| *slot))
+            .map(|(i, _, _)| *i);
+
+        let i = match assigned {
+            Some(i) => i,
+            None => {
+                let slot = slots.iter().map(|(_, slot, _)| slot + 1).max().unwrap_or(0);
+                let client = match get_dns_provider(&self.config, &host.domain) {
+                    Ok(client) => client,
+                    Err(err) => {
+                        error!("Failed to start a record for {ip} on {}: {err:#}", host.name);
+                        return;
+                    }
+                };
+                let record = spawn_record(host, slot, family, client, &self.config, self.state.clone());
+                info!("Publishing {ip} as {}", record.host.fqdn());
+                records.push(record);
+                records.len() - 1
+            }
+        };
+
+        let record = &mut records[i];
+        record.addr = Some(ip);
+        if let Err(err) = record.tx.unbounded_send(Command::Publish(Target::Addr(ip))) {
+            error!("Updater for {} has stopped: {err}", record.host.name);
+        }
+    }
+
+    /// Wait until no record has an update in progress, returning the status
+    /// of each.
+    ///
+    /// Failed updates waiting to be retried count as settled.
+    pub async fn settle(&self) -> Vec<(HostConfig, AddressFamily, Status)> {
+        let records = self.records.borrow().iter()
+            .map(|r| (r.host.clone(), r.family, r.tx.clone()))
+            .collect::<Vec<_>>();
+        let replies = records.into_iter().map(|(host, family, record)| async move {
+            let (tx, rx) = oneshot::channel();
+            let status = match record.unbounded_send(Command::Settle(tx)) {
+                Ok(()) => rx.await.unwrap_or(Status::Failed),
+                Err(_) => Status::Failed,
+            };
+            (host, family, status)
+        });
+        join_all(replies).await
+    }
+
+    /// Read the current addresses of `iface` and publish them, returning the
+    /// address families in use that have no address yet.
+    pub async fn publish_iface(&self, iface: &str) -> Vec<AddressFamily> {
+        let mut families = Vec::new();
+        for record in self.records.borrow().iter()
+            .filter(|r| r.host.iface == iface)
+        {
+            if !families.contains(&record.family) {
+                families.push(record.family);
+            }
+        }
+
+        let mut missing = Vec::new();
+        for family in families {
+            match netlink::get_if_addrs(iface, family).await {
+                Ok(addrs) if !addrs.is_empty() => self.publish(iface, &addrs),
+                Ok(_) => missing.push(family),
+                Err(err) => {
+                    warn!("Error getting {family:?} IP on {iface}: {err:#}");
+                    missing.push(family);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Read the current addresses of every interface in use and publish
+    /// them, returning the interfaces and families that have no address yet.
+    pub async fn publish_current(&self) -> Vec<(String, AddressFamily)> {
+        let mut ifaces = Vec::new();
+        for record in self.records.borrow().iter() {
+            if !ifaces.contains(&record.host.iface) {
+                ifaces.push(record.host.iface.clone());
+            }
+        }
+
+        let mut missing = Vec::new();
+        for iface in ifaces {
+            for family in self.publish_iface(&iface).await {
+                missing.push((iface.clone(), family));
+            }
+        }
+        missing
+    }
+
+    /// Act on a monitored interface changing state.
+    ///
+    /// When an interface comes up, possibly after being recreated, its
+    /// addresses are re-read and published as on startup. Records are left
+    /// alone when it goes down or is removed; any addresses it loses are
+    /// reported separately and handled by the host's `on_delete` policy.
+    pub async fn link_changed(&self, change: &LinkChange) {
+        match change.state {
+            LinkState::Up if change.iface == AUTO_IFACE => {
+                info!("Default route changed; syncing its addresses");
+                self.publish_iface(&change.iface).await;
+            }
+            LinkState::Up => {
+                info!("Interface {} is up; syncing its addresses", change.iface);
+                self.publish_iface(&change.iface).await;
+            }
+            LinkState::Down => info!("Interface {} is down", change.iface),
+            LinkState::Removed if change.iface == AUTO_IFACE => info!("There is no default route"),
+            LinkState::Removed => info!("Interface {} was removed", change.iface),
+        }
+    }
+
+    /// Force every record to be re-read from the provider and corrected if
+    /// necessary, using the current interface addresses.
+    pub async fn resync(&self) {
+        for record in self.records.borrow().iter() {
+            if let Err(err) = record.tx.unbounded_send(Command::Resync) {
+                error!("Updater for {} has stopped: {err}", record.host.name);
+            }
+        }
+        self.publish_current().await;
+    }
+
+    /// Wait up to `timeout` for in-flight updates to finish, then flush the
+    /// state file.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let settled = select(Box::pin(self.settle()), Box::pin(timer(timeout))).await;
+        if let Either::Right(_) = settled {
+            warn!("Timed out waiting for DNS updates to finish");
+        }
+
+        if let Some(state) = &self.state
+            && let Err(err) = state.borrow().save()
+        {
+            warn!("Failed to save state: {err:#}");
+        }
+    }
+
+    /// Notify the records on `iface` that `addr` has been removed.
+    ///
+    /// Records using an external discovery source are unaffected, as the
+    /// public address may not have changed. For hosts publishing all their
+    /// addresses, only the record assigned `addr` is notified, and it is
+    /// freed for the next new address.
+    pub fn remove(&self, iface: &str, addr: &IfAddr) {
+        for record in self.records.borrow_mut().iter_mut()
+            .filter(|r| r.host.iface == iface)
+            .filter(|r| r.host.source == AddrSource::Netlink)
+            .filter(|r| (r.family == AddressFamily::Inet6) == addr.ip.is_ipv6())
+        {
+            if record.host.select == AddrSelect::All {
+                if record.addr != Some(addr.ip) {
+                    continue;
+                }
+                record.addr = None;
+            }
+            if let Err(err) = record.tx.unbounded_send(Command::Deleted(addr.ip)) {
+                error!("Updater for {} has stopped: {err}", record.host.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_record(family: AddressFamily, on_delete: &str) -> Record {
+        let fragment = format!(r#"
+            {{
+                iface = "eth0"
+                ddns = {{
+                    provider = {{
+                      name = "digitalocean"
+                      key = "a_key"
+                    }}
+                    domain = "example.com"
+                    host = "test"
+                    on_delete = {on_delete}
+                }}
+            }} "#);
+        let config = corn::from_str::<Config>(&fragment).unwrap();
+        let host = config.hosts().remove(0);
+        let client = get_dns_provider(&config, &host.domain).unwrap();
+        Record::new(host, family, client, &config, None)
+    }
+
+    #[test]
+    fn test_delete_starts_grace() {
+        let ip = "198.51.100.1".parse().unwrap();
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "delete" }"#);
+        record.local = Some(ip);
+
+        record.handle_command(Command::Deleted("198.51.100.2".parse().unwrap()));
+        assert!(record.grace_timer.is_none());
+
+        record.handle_command(Command::Deleted(ip));
+        assert!(record.grace_timer.is_some());
+        assert_eq!(record.deletion_target(), Some(Target::Remove));
+
+        let new_ip = "198.51.100.3".parse().unwrap();
+        record.handle_command(Command::Publish(Target::Addr(new_ip)));
+        assert!(record.grace_timer.is_none());
+        assert_eq!(record.pending, Some(Target::Addr(new_ip)));
+    }
+
+    #[test]
+    fn test_delete_pending() {
+        let ip = "198.51.100.1".parse().unwrap();
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "ignore" }"#);
+
+        record.handle_command(Command::Publish(Target::Addr(ip)));
+        record.handle_command(Command::Deleted(ip));
+        assert_eq!(record.pending, None);
+        assert!(record.grace_timer.is_none());
+        assert_eq!(record.deletion_target(), None);
+    }
+
+    #[test]
+    fn test_fallback_target_family() {
+        let policy = r#"{ action = "fallback" addresses = [ "192.0.2.1" "2001:db8::1" ] }"#;
+
+        let record = test_record(AddressFamily::Inet, policy);
+        assert_eq!(record.deletion_target(), Some(Target::Fallback("192.0.2.1".parse().unwrap())));
+
+        let record = test_record(AddressFamily::Inet6, policy);
+        assert_eq!(record.deletion_target(), Some(Target::Fallback("2001:db8::1".parse().unwrap())));
+
+        let record = test_record(AddressFamily::Inet6, r#"{ action = "fallback" addresses = [ "192.0.2.1" ] }"#);
+        assert_eq!(record.deletion_target(), None);
+    }
+
+    #[test]
+    fn test_backoff_grows() {
+        let retry = Retry { initial_delay: 2, max_delay: 60 };
+        for attempt in 0..4 {
+            let full = Duration::from_secs(2 << attempt);
+            let delay = backoff(&retry, attempt);
+            assert!(delay <= full, "{delay:?} > {full:?}");
+            assert!(delay >= full / 2, "{delay:?} < {full:?}/2");
+        }
+    }
+
+    #[test]
+    fn test_backoff_capped() {
+        let retry = Retry { initial_delay: 2, max_delay: 60 };
+        for attempt in [5, 10, 64, u32::MAX] {
+            let delay = backoff(&retry, attempt);
+            assert!(delay <= Duration::from_secs(60));
+            assert!(delay >= Duration::from_secs(30));
+        }
+    }
+
+    #[test]
+    fn test_settle_idle() -> Result<()> {
+        let config = corn::from_str::<Config>(r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    hosts = [ "www" "mail" ]
+                }
+            } "#)?;
+        compio::runtime::Runtime::new()?.block_on(async {
+            let updater = Updater::new(&config)?;
+            let statuses = updater.settle().await;
+            assert_eq!(statuses.len(), 2);
+            assert!(statuses.iter().all(|(_, _, status)| *status == Status::Idle));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "ignore" }"#);
+        record.handle_command(Command::Resync);
+        assert_eq!(record.pending, None);
+
+        let ip = "192.0.2.1".parse().unwrap();
+        record.published = Some(ip);
+        record.last_target = Some(Target::Addr(ip));
+        record.retry_timer = Some(timer(Duration::from_secs(60)));
+        record.handle_command(Command::Resync);
+        assert_eq!(record.published, None);
+        assert_eq!(record.pending, Some(Target::Addr(ip)));
+        assert!(record.retry_timer.is_none());
+    }
+
+    #[test]
+    fn test_skipped_not_reconciled() {
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "ignore" }"#);
+        record.reconcile_interval = Some(Duration::from_secs(60));
+
+        let ip = "192.0.2.1".parse().unwrap();
+        record.published = Some(ip);
+        record.applied(Target::Addr(ip), Outcome::Unchanged);
+        assert_eq!(record.last_target, Some(Target::Addr(ip)));
+        assert!(record.reconcile_timer.is_some());
+
+        let private = "10.0.0.1".parse().unwrap();
+        record.applied(Target::Addr(private), Outcome::Skipped);
+        assert_eq!(record.last_target, None);
+        assert!(record.reconcile_timer.is_none());
+        assert_eq!(record.status, Status::Synced(Outcome::Skipped));
+    }
+
+    #[test]
+    fn test_reconcile_drift() {
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "ignore" }"#);
+        let ip = "192.0.2.1".parse().unwrap();
+        record.published = Some(ip);
+
+        record.compare_upstream(Target::Addr(ip), Some(ip));
+        assert_eq!(record.pending, None);
+        assert_eq!(record.published, Some(ip));
+
+        let other = "192.0.2.2".parse().unwrap();
+        record.compare_upstream(Target::Addr(ip), Some(other));
+        assert_eq!(record.pending, Some(Target::Addr(ip)));
+        assert_eq!(record.published, Some(other));
+
+        record.pending = None;
+        record.published = Some(ip);
+        record.compare_upstream(Target::Addr(ip), None);
+        assert_eq!(record.pending, Some(Target::Addr(ip)));
+        assert_eq!(record.published, None);
+    }
+
+    #[test]
+    fn test_reconcile_discover() {
+        let mut record = test_record(AddressFamily::Inet, r#"{ action = "ignore" }"#);
+        let ip = "192.0.2.1".parse().unwrap();
+        record.published = Some(ip);
+
+        // Discovered addresses are re-checked even without drift.
+        record.compare_upstream(Target::Discover, Some(ip));
+        assert_eq!(record.pending, Some(Target::Discover));
+    }
+
+    #[test]
+    fn test_shutdown_flushes_state() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        let config = corn::from_str::<Config>(&format!(r#"
+            {{
+                iface = "eth0"
+                state_file = "{}"
+                ddns = {{
+                    provider = {{
+                      name = "digitalocean"
+                      key = "a_key"
+                    }}
+                    domain = "example.com"
+                    host = "www"
+                }}
+            }} "#, path.display()))?;
+        compio::runtime::Runtime::new()?.block_on(async {
+            let updater = Updater::new(&config)?;
+            updater.shutdown(Duration::from_secs(1)).await;
+            Ok::<_, anyhow::Error>(())
+        })?;
+        assert!(path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dry_run_not_saved() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("state.json");
+        let config = corn::from_str::<Config>(&format!(r#"
+            {{
+                iface = "eth0"
+                dry_run = true
+                state_file = "{}"
+                ddns = {{
+                    provider = {{
+                      name = "digitalocean"
+                      key = "a_key"
+                    }}
+                    domain = "example.com"
+                    host = "www"
+                }}
+            }} "#, path.display()))?;
+        let host = config.hosts().remove(0);
+        let client = get_dns_provider(&config, &host.domain)?;
+        let mut record = Record::new(host.clone(), AddressFamily::Inet, client, &config, load_state(&config));
+
+        let ip = "192.0.2.1".parse()?;
+        record.published = Some(ip);
+        record.applied(Target::Addr(ip), Outcome::Created);
+
+        let key = record_key(&host, AddressFamily::Inet);
+        assert!(StateFile::load(&path).get(&key).is_none());
+        assert!(record.state.unwrap().borrow().get(&key).is_none());
+        Ok(())
+    }
+
+    fn hosts_config(hosts: &str, key: &str) -> Config {
+        corn::from_str::<Config>(&format!(r#"
+            {{
+                iface = "eth0"
+                ddns = {{
+                    provider = {{
+                      name = "digitalocean"
+                      key = "{key}"
+                    }}
+                    domain = "example.com"
+                    hosts = {hosts}
+                }}
+            }} "#)).unwrap()
+    }
+
+    #[test]
+    fn test_reload_diff() -> Result<()> {
+        compio::runtime::Runtime::new()?.block_on(async {
+            let mut updater = Updater::new(&hosts_config(r#"[ "www" "mail" ]"#, "a_key"))?;
+            let www = updater.records.borrow()[0].tx.clone();
+            let mail = updater.records.borrow()[1].tx.clone();
+
+            updater.reload(&hosts_config(r#"[ "www" { name = "mail" iface = "eth1" } "vpn" ]"#, "a_key"))?;
+            let records = updater.records.borrow();
+            let names = records.iter()
+                .map(|r| (r.host.name.as_str(), r.host.iface.as_str()))
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec![("www", "eth0"), ("mail", "eth1"), ("vpn", "eth0")]);
+            assert!(records[0].tx.same_receiver(&www));
+            assert!(!records[1].tx.same_receiver(&mail));
+            Ok(())
+        })
+    }
+
+    fn addr(ip: &str) -> IfAddr {
+        use rtnetlink::packet_route::address::{AddressFlags, AddressScope};
+        IfAddr {
+            ip: ip.parse().unwrap(),
+            peer: None,
+            prefix_len: 24,
+            label: None,
+            scope: AddressScope::Universe,
+            flags: AddressFlags::Permanent,
+        }
+    }
+
+    #[test]
+    fn test_publish_all() -> Result<()> {
+        let config = corn::from_str::<Config>(r#"
+            {
+                iface = "eth0"
+                ddns = {
+                    provider = {
+                      name = "digitalocean"
+                      key = "a_key"
+                    }
+                    domain = "example.com"
+                    select = "all"
+                    host = "www"
+                }
+            } "#)?;
+        let assigned = |updater: &Updater| updater.records.borrow().iter()
+            .map(|r| (r.host.name.clone(), r.addr.map(|ip| ip.to_string())))
+            .collect::<Vec<_>>();
+        let slot = |name: &str, ip: Option<&str>| (name.to_string(), ip.map(str::to_string));
+
+        compio::runtime::Runtime::new()?.block_on(async {
+            let updater = Updater::new(&config)?;
+            updater.publish("eth0", &[addr("192.0.2.1"), addr("192.0.2.2")]);
+            updater.publish("eth0", &[addr("192.0.2.1")]);
+            assert_eq!(assigned(&updater), vec![
+                slot("www", Some("192.0.2.1")),
+                slot("www-2", Some("192.0.2.2")),
+            ]);
+
+            // A replacement address takes over the freed record.
+            updater.remove("eth0", &addr("192.0.2.1"));
+            assert_eq!(assigned(&updater)[0], slot("www", None));
+            updater.publish("eth0", &[addr("192.0.2.3"), addr("192.0.2.4")]);
+            assert_eq!(assigned(&updater), vec![
+                slot("www", Some("192.0.2.3")),
+                slot("www-2", Some("192.0.2.2")),
+                slot("www-3", Some("192.0.2.4")),
+            ]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_reload_provider_change() -> Result<()> {
+        compio::runtime::Runtime::new()?.block_on(async {
+            let mut updater = Updater::new(&hosts_config(r#"[ "www" ]"#, "a_key"))?;
+            let www = updater.records.borrow()[0].tx.clone();
+
+            updater.reload(&hosts_config(r#"[ "www" ]"#, "a_key"))?;
+            assert!(updater.records.borrow()[0].tx.same_receiver(&www));
+
+            updater.reload(&hosts_config(r#"[ "www" ]"#, "new_key"))?;
+            assert!(!updater.records.borrow()[0].tx.same_receiver(&www));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_shared_redacted() {
+        let shared = Shared::new(&hosts_config(r#"[ "www" ]"#, "a_key"));
+        assert!(!format!("{shared:?}").contains("a_key"));
+    }
+}